@@ -1,6 +1,18 @@
-use crate::{interpreter::Interpreter, literal::Literal};
+use crate::{interpreter::{Interpreter, RuntimeException}, lexer::Token, literal::Literal};
 
 pub trait Callable {
+    /// The number of arguments that must be supplied.
     fn arity(&self) -> usize;
-    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Literal>) -> Literal;
+
+    /// The number of arguments that may be supplied.
+    fn max_arity(&self) -> usize {
+        self.arity()
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeException>;
 }
@@ -1,43 +1,43 @@
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    rc::Rc,
+};
 
 use crate::{
-    interpreter::{RuntimeError, RuntimeException},
+    interpreter::{RuntimeError, RuntimeErrorKind, RuntimeException},
     lexer::Token,
     literal::Literal,
+    ordered_map::OrderedMap,
 };
 
 #[derive(Debug, Clone)]
 pub struct Environment {
-    pub values: HashMap<String, Literal>,
-    enclosing: Option<Box<Environment>>,
+    pub values: OrderedMap<Literal>,    // in the order the names were first defined
+    constants: HashSet<String>,
+    enclosing: Option<Rc<RefCell<Environment>>>, // shared with everything else that encloses in it
 }
 
 impl Environment {
-    pub fn new(enclosing: Option<Environment>) -> Self {
-        if let Some(enclosing) = enclosing {
-            Environment {
-                values: HashMap::new(),
-                enclosing: Some(Box::new(enclosing)),
-            }
-        } else {
-            Environment {
-                values: HashMap::new(),
-                enclosing: None,
-            }
-        }
-    }
-
-    /// This function can be used to get the enclosing environment whose values may have been changed by the current environment statements.
-    pub fn get_enclosing_environment(&mut self) -> Option<Self> {
-        if let Some(enclosing) = self.enclosing.clone() {
-            Some(*enclosing)
-        } else {
-            None
+    /// Create a scope nested in `enclosing`. The enclosing scope is linked rather than copied,
+    /// so entering a scope is cheap and assignments to outer names are seen by everyone.
+    pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
+        Environment {
+            values: OrderedMap::new(),
+            constants: HashSet::new(),
+            enclosing,
         }
     }
 
     /// Create a binding of a name with a value.
     pub fn define(&mut self, name: String, value: Literal) {
+        self.constants.remove(&name);
+        self.values.insert(name, value);
+    }
+
+    /// Create a binding of a name with a value that cannot be reassigned.
+    pub fn define_constant(&mut self, name: String, value: Literal) {
+        self.constants.insert(name.clone());
         self.values.insert(name, value);
     }
 
@@ -47,13 +47,10 @@ impl Environment {
             Ok(value.clone())
         } else {
             match &self.enclosing {
-                Some(enclosing) => enclosing.get(name),
+                Some(enclosing) => enclosing.borrow().get(name),
                 None => {
                     let message = format!("undefined variable '{}'", name.lexeme);
-                    Err(RuntimeException::Error(RuntimeError {
-                        token: name,
-                        message,
-                    }))
+                    Err(RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::UndefinedVariable, name, message)))
                 }
             }
         }
@@ -61,28 +58,41 @@ impl Environment {
 
     /// Assign new value to an existing name in the environment.
     pub fn assign(&mut self, name: Token, value: Literal) -> Result<(), RuntimeException> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme, value);
+        if self.constants.contains(&*name.lexeme) {
+            let message = format!("Cannot assign to constant '{}'", name.lexeme);
+            Err(RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::AssignToConstant, name, message)))
+        } else if let Some(existing) = self.values.get_mut(&name.lexeme) {
+            *existing = value;
             Ok(())
         } else {
             match &mut self.enclosing {
                 Some(enclosing) => {
                     // self.values.insert(name.lexeme.clone(), enclosing.get(name)?);
                     // enclosing.values.insert(name.lexeme, value);
-                    enclosing.assign(name, value)?;
+                    enclosing.borrow_mut().assign(name, value)?;
                     Ok(())
                 }
                 None => {
                     let message = format!("undefined variable '{}'", name.lexeme);
-                    Err(RuntimeException::Error(RuntimeError {
-                        token: name,
-                        message,
-                    }))
+                    Err(RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::UndefinedVariable, name, message)))
                 }
             }
         }
     }
 
+    /// Every binding visible from this scope, innermost scope first and each scope's names in
+    /// the order they were defined. A shadowed name appears once per scope that binds it, and
+    /// the first of those is the one the name refers to.
+    pub fn snapshot(&self) -> Vec<(String, Literal)> {
+        let mut bindings: Vec<(String, Literal)> = self.values.clone().into_iter().collect();
+
+        if let Some(enclosing) = &self.enclosing {
+            bindings.extend(enclosing.borrow().snapshot());
+        }
+
+        bindings
+    }
+
     pub fn display(&self) {
         for (string, literal) in self.values.clone() {
             println!("{} => {}", string, literal.to_string());
@@ -90,11 +100,8 @@ impl Environment {
 
         println!("___________________________");
 
-        match &self.enclosing {
-            Some(enclosing) => {
-                enclosing.display();
-            }
-            None => ()
+        if let Some(enclosing) = &self.enclosing {
+            enclosing.borrow().display();
         }
     }
 }
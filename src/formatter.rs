@@ -0,0 +1,453 @@
+use crate::{
+    lexer::{Token, TokenType},
+    literal::Literal,
+    stmt::{Expr, Stmt},
+};
+
+const INDENT: &str = "    ";
+
+// How tightly each kind of expression binds, loosest first, following the parser's
+// grammar. An operand that binds more loosely than its position needs is put in parentheses.
+const ASSIGNMENT: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const EQUALITY: u8 = 4;
+const COMPARISON: u8 = 5;
+const TERM: u8 = 6;
+const FACTOR: u8 = 7;
+const UNARY: u8 = 8;
+const POWER: u8 = 9;
+const POSTFIX: u8 = 10; // calls, indexing and property access
+const PRIMARY: u8 = 11;
+
+/// Turn a parsed program back into source, laid out the same way whatever the original
+/// looked like: one statement per line, four spaces of indentation, spaces around binary
+/// operators. Parsing the result gives the same program.
+pub fn format(stmts: &[Stmt]) -> String {
+    let mut formatter = Formatter { out: String::new(), indent: 0 };
+    formatter.statements(stmts);
+    formatter.out
+}
+
+struct Formatter {
+    out: String,
+    indent: usize,
+}
+
+impl Formatter {
+    /// Write statements one per line, with a blank line around function declarations.
+    fn statements(&mut self, stmts: &[Stmt]) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            let function = matches!(stmt, Stmt::Function(..));
+            if i > 0 && (function || matches!(stmts[i - 1], Stmt::Function(..))) {
+                self.out.push('\n');
+            }
+
+            self.line_start();
+            self.statement(stmt);
+            self.out.push('\n');
+        }
+    }
+
+    fn line_start(&mut self) {
+        self.out.push_str(&INDENT.repeat(self.indent));
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                let expr = self.expression(expr);
+                self.out.push_str(&format!("{};", expr));
+            }
+            Stmt::Print(_, expr) => {
+                let expr = self.expression(expr);
+                self.out.push_str(&format!("print {};", expr));
+            }
+            Stmt::If(arms, else_branch) => {
+                for (i, (condition, branch)) in arms.iter().enumerate() {
+                    if i > 0 {
+                        self.before_else(&arms[i - 1].1);
+                        self.out.push_str("else ");
+                    }
+                    let condition = self.expression(condition);
+                    self.out.push_str(&format!("if ({})", condition));
+                    self.body(branch);
+                }
+
+                if **else_branch != Stmt::None {
+                    if let Some((_, branch)) = arms.last() {
+                        self.before_else(branch);
+                    }
+                    self.out.push_str("else");
+                    self.body(else_branch);
+                }
+            }
+            Stmt::While(condition, body, increment, else_branch, label) => {
+                self.label(label);
+                let condition = self.expression(condition);
+                if *increment == Expr::None {
+                    self.out.push_str(&format!("while ({})", condition));
+                } else {
+                    let increment = self.expression(increment);
+                    self.out.push_str(&format!("for (; {}; {})", condition, increment));
+                }
+                self.loop_rest(body, else_branch);
+            }
+            Stmt::Repeat(_, count, body, label) => {
+                self.label(label);
+                let count = self.expression(count);
+                self.out.push_str(&format!("repeat ({})", count));
+                self.body(body);
+            }
+            Stmt::DoWhile(body, condition, label) => {
+                self.label(label);
+                self.out.push_str("do");
+                self.body(body);
+                self.before_else(body);
+                let condition = self.expression(condition);
+                self.out.push_str(&format!("while ({});", condition));
+            }
+            Stmt::Switch(subject, cases, default) => {
+                let subject = self.expression(subject);
+                self.out.push_str(&format!("switch ({}) {{\n", subject));
+                self.indent += 1;
+                for (value, stmts) in cases {
+                    self.line_start();
+                    let value = self.expression(value);
+                    self.out.push_str(&format!("case {}:\n", value));
+                    self.arm(stmts);
+                }
+                if let Some(stmts) = default {
+                    self.line_start();
+                    self.out.push_str("default:\n");
+                    self.arm(stmts);
+                }
+                self.indent -= 1;
+                self.line_start();
+                self.out.push('}');
+            }
+            Stmt::Function(name, parameters, rest, body) => {
+                let function = self.function(parameters, rest, body);
+                self.out.push_str(&format!("fn {}{}", name.lexeme, function));
+                if body.arrow_value().is_some() {
+                    self.out.push(';');
+                }
+            }
+            Stmt::Return(_, value) => match value {
+                Expr::None => self.out.push_str("return;"),
+                value => {
+                    let value = self.expression(value);
+                    self.out.push_str(&format!("return {};", value));
+                }
+            },
+            Stmt::Break(_, label) => self.jump("break", label),
+            Stmt::Continue(_, label) => self.jump("continue", label),
+            Stmt::Var(name, initializer) => {
+                let declaration = self.declaration(name, initializer);
+                self.out.push_str(&format!("let {};", declaration));
+            }
+            Stmt::LetElse(name, initializer, else_branch) => {
+                let initializer = self.expression(initializer);
+                self.out.push_str(&format!("let {} = {} else ", name.lexeme, initializer));
+                self.statement(else_branch);
+            }
+            Stmt::VarMulti(declarations) => {
+                let declarations: Vec<String> = declarations
+                    .iter()
+                    .map(|(name, initializer)| self.declaration(name, initializer))
+                    .collect();
+                self.out.push_str(&format!("let {};", declarations.join(", ")));
+            }
+            Stmt::Const(name, initializer) => {
+                let initializer = self.expression(initializer);
+                self.out.push_str(&format!("const {} = {};", name.lexeme, initializer));
+            }
+            Stmt::Destructure(_, names, rest, initializer) => {
+                let mut names: Vec<String> = names.iter().map(|name| name.lexeme.to_string()).collect();
+                if let Some(rest) = rest {
+                    names.push(format!("...{}", rest.lexeme));
+                }
+                let initializer = self.expression(initializer);
+                self.out.push_str(&format!("let [{}] = {};", names.join(", "), initializer));
+            }
+            Stmt::Block(stmts) => {
+                // `for (let i = 0; ...)` is parsed into a block holding the declaration and the loop
+                if let [initializer @ (Stmt::Var(..) | Stmt::VarMulti(..) | Stmt::Expression(_)), Stmt::While(condition, body, increment, else_branch, label)] =
+                    stmts.as_slice()
+                {
+                    if *increment != Expr::None {
+                        self.label(label);
+                        self.out.push_str("for (");
+                        self.statement(initializer);
+                        let condition = self.expression(condition);
+                        let increment = self.expression(increment);
+                        self.out.push_str(&format!(" {}; {})", condition, increment));
+                        self.loop_rest(body, else_branch);
+                        return;
+                    }
+                }
+                self.block(stmts);
+            }
+            Stmt::None => (),
+        }
+    }
+
+    /// Write `{`, the statements indented one level deeper, and `}` on a line of its own.
+    fn block(&mut self, stmts: &[Stmt]) {
+        if stmts.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push_str("{\n");
+        self.indent += 1;
+        self.statements(stmts);
+        self.indent -= 1;
+        self.line_start();
+        self.out.push('}');
+    }
+
+    /// The body of an `if`, loop or `else`: a block opens on the same line, and a
+    /// single statement follows on the same line too.
+    fn body(&mut self, stmt: &Stmt) {
+        self.out.push(' ');
+        self.statement(stmt);
+    }
+
+    /// What separates a body from a following `else` or `while`: a space after a
+    /// closing brace, or a new line after a single statement.
+    fn before_else(&mut self, body: &Stmt) {
+        if self.out.ends_with('}') && matches!(body, Stmt::Block(_)) {
+            self.out.push(' ');
+        } else {
+            self.out.push('\n');
+            self.line_start();
+        }
+    }
+
+    fn loop_rest(&mut self, body: &Stmt, else_branch: &Stmt) {
+        self.body(body);
+        if *else_branch != Stmt::None {
+            self.before_else(body);
+            self.out.push_str("else");
+            self.body(else_branch);
+        }
+    }
+
+    fn arm(&mut self, stmts: &[Stmt]) {
+        self.indent += 1;
+        self.statements(stmts);
+        self.indent -= 1;
+    }
+
+    fn label(&mut self, label: &Option<Token>) {
+        if let Some(label) = label {
+            self.out.push_str(&format!("{}: ", label.lexeme));
+        }
+    }
+
+    fn jump(&mut self, keyword: &str, label: &Option<Token>) {
+        match label {
+            Some(label) => self.out.push_str(&format!("{} {};", keyword, label.lexeme)),
+            None => self.out.push_str(&format!("{};", keyword)),
+        }
+    }
+
+    /// `name = value`, or just `name` when there is no initializer, which the parser
+    /// records as `nil`.
+    fn declaration(&mut self, name: &Token, initializer: &Expr) -> String {
+        match initializer {
+            Expr::Literal(Literal::Null) => name.lexeme.to_string(),
+            initializer => format!("{} = {}", name.lexeme, self.expression(initializer)),
+        }
+    }
+
+    /// The parameters and body of a function, from the opening parenthesis on.
+    fn function(&mut self, parameters: &[(Token, Option<Expr>)], rest: &Option<Token>, body: &Stmt) -> String {
+        let mut list: Vec<String> = parameters
+            .iter()
+            .map(|(name, default)| match default {
+                Some(default) => format!("{} = {}", name.lexeme, self.expression(default)),
+                None => name.lexeme.to_string(),
+            })
+            .collect();
+        if let Some(rest) = rest {
+            list.push(format!("...{}", rest.lexeme));
+        }
+
+        if let Some(value) = body.arrow_value() {
+            return format!("({}) => {}", list.join(", "), self.expression(value));
+        }
+
+        // the body goes through `out` so it is indented like the surrounding code
+        let written = std::mem::take(&mut self.out);
+        match body {
+            Stmt::Block(stmts) => self.block(stmts),
+            body => self.statement(body),
+        }
+        let body = std::mem::replace(&mut self.out, written);
+
+        format!("({}) {}", list.join(", "), body)
+    }
+
+    fn expression(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Literal(literal) => Self::literal(literal),
+            Expr::Variable(name) => name.lexeme.to_string(),
+            Expr::Grouping(expr) => format!("({})", self.expression(expr)),
+            Expr::Assign(name, value) => format!("{} = {}", name.lexeme, self.expression(value)),
+            Expr::Logical(left, operator, right) | Expr::Binary(left, operator, right) => {
+                let precedence = Self::precedence(expr);
+                let (left_min, right_min) = if operator.token_type == TokenType::StarStar {
+                    // `**` groups to the right, and its exponent may start with a unary operator
+                    (POSTFIX, UNARY)
+                } else {
+                    (precedence, precedence + 1)
+                };
+                let left = self.operand(left, left_min);
+                let right = self.trailing_operand(right, right_min);
+                format!("{} {} {}", left, Self::operator(operator), right)
+            }
+            Expr::Unary(operator, right) => {
+                format!("{}{}", operator.lexeme, self.trailing_operand(right, UNARY))
+            }
+            Expr::Call(callee, _, arguments, keywords) => {
+                let callee = self.operand(callee, POSTFIX);
+                let mut list: Vec<String> = arguments.iter().map(|argument| self.expression(argument)).collect();
+                for (name, argument) in keywords {
+                    list.push(format!("{}: {}", name.lexeme, self.expression(argument)));
+                }
+                format!("{}({})", callee, list.join(", "))
+            }
+            Expr::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|element| self.expression(element)).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Expr::Map(_, entries) => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", self.expression(key), self.expression(value)))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Expr::If(condition, then_value, else_value) => format!(
+                "if ({}) {} else {}",
+                self.expression(condition),
+                self.expression(then_value),
+                self.expression(else_value)
+            ),
+            Expr::Lambda(lambda) => format!("fn {}", self.function(&lambda.parameters, &lambda.rest, &lambda.body)),
+            Expr::Index(object, _, index) => {
+                format!("{}[{}]", self.operand(object, POSTFIX), self.expression(index))
+            }
+            Expr::Get(object, name, safe) => {
+                let access = if *safe { "?." } else { "." };
+                format!("{}{}{}", self.operand(object, POSTFIX), access, name.lexeme)
+            }
+            Expr::Slice(object, _, start, end) => {
+                let object = self.operand(object, POSTFIX);
+                format!("{}[{}..{}]", object, self.expression(start), self.expression(end))
+            }
+            Expr::SetIndex(object, _, index, value) => format!(
+                "{}[{}] = {}",
+                self.operand(object, POSTFIX),
+                self.expression(index),
+                self.expression(value)
+            ),
+            Expr::None => String::new(),
+        }
+    }
+
+    /// An operand, in parentheses if it binds more loosely than `min` allows.
+    fn operand(&mut self, expr: &Expr, min: u8) -> String {
+        let text = self.expression(expr);
+        if Self::precedence(expr) < min {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    /// The last operand of an expression. An `if` expression or an arrow function can go
+    /// there without parentheses, since nothing comes after it to be taken in by its end.
+    fn trailing_operand(&mut self, expr: &Expr, min: u8) -> String {
+        match expr {
+            Expr::If(..) | Expr::Lambda(..) => self.expression(expr),
+            expr => self.operand(expr, min),
+        }
+    }
+
+    fn precedence(expr: &Expr) -> u8 {
+        match expr {
+            Expr::Assign(..) | Expr::SetIndex(..) | Expr::If(..) => ASSIGNMENT,
+            // an arrow body would take in anything written after it
+            Expr::Lambda(lambda) if lambda.body.arrow_value().is_some() => ASSIGNMENT,
+            Expr::Logical(_, operator, _) | Expr::Binary(_, operator, _) => match operator.token_type {
+                TokenType::Or => OR,
+                TokenType::And => AND,
+                TokenType::EqualEqual | TokenType::BangEqual => EQUALITY,
+                TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+                    COMPARISON
+                }
+                TokenType::Plus | TokenType::Minus => TERM,
+                TokenType::StarStar => POWER,
+                _ => FACTOR,
+            },
+            Expr::Unary(..) => UNARY,
+            Expr::Call(..) | Expr::Index(..) | Expr::Get(..) | Expr::Slice(..) => POSTFIX,
+            _ => PRIMARY,
+        }
+    }
+
+    /// `&&` and `||` are written as `and` and `or`.
+    fn operator(operator: &Token) -> String {
+        match operator.token_type {
+            TokenType::And => "and".to_string(),
+            TokenType::Or => "or".to_string(),
+            _ => operator.lexeme.to_string(),
+        }
+    }
+
+    fn literal(literal: &Literal) -> String {
+        match literal {
+            // written out in full, since the lexer doesn't read exponents
+            Literal::Number(x) => format!("{}", x),
+            Literal::String(text) => format!("\"{}\"", text),
+            Literal::Null => "nil".to_string(),
+            literal => literal.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    fn format_source(source: &str) -> String {
+        let stmts = Parser::from_lexer(Lexer::new(source)).parse().expect("source should parse");
+        format(&stmts)
+    }
+
+    #[test]
+    fn formatting_formatted_source_changes_nothing() {
+        let source = r#"
+fn  fib(n){if(n<2)return n;else{return fib(n-1)+fib(n-2);}}
+let xs=[1,2,(3+4)*5];let m={"a":1,"b":[2,3]};
+for(let i=0;i<3;i=i+1){ { let y=-(i- 1)*2**3**2; print y; } }
+while(false){break;} else {print "done";}
+let f = fn (a, b = 2, ...rest) { return a - (b - 1); };
+print f(1) |> fib;
+switch (xs[0]) { case 1: print "one"; default: print "other"; }
+print !(true and false) or nil;
+let [p, q] = [1, 2];
+const c = if (p < q) 1 else 2;
+"#;
+        let once = format_source(source);
+        let twice = format_source(&once);
+
+        assert_eq!(once, twice);
+        assert!(once.contains("let xs = [1, 2, (3 + 4) * 5];\n"), "{}", once);
+        assert!(once.contains("        let y = -(i - 1) * 2 ** 3 ** 2;\n"), "{}", once);
+    }
+}
@@ -1,54 +1,194 @@
+use std::{cell::RefCell, rc::Rc};
+
 use crate::{
-    callable::Callable, environment::Environment, interpreter::{Interpreter, RuntimeException}, lexer::Token,
-    literal::Literal, stmt::Stmt,
+    callable::Callable, environment::Environment, interpreter::{Interpreter, RuntimeError, RuntimeErrorKind, RuntimeException}, lexer::Token,
+    literal::Literal, stmt::{Expr, Stmt},
 };
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Function {
     name: Token,
-    parameters: Vec<Token>,
+    parameters: Vec<(Token, Option<Expr>)>,   // name, default value
+    rest: Option<Token>,
     body: Stmt,
+    closure: Option<Rc<RefCell<Environment>>>, // the scope the body runs in, or the caller's when there is none
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        let same_closure = match (&self.closure, &other.closure) {
+            (Some(x), Some(y)) => Rc::ptr_eq(x, y),
+            (x, y) => x.is_none() && y.is_none(),
+        };
+
+        same_closure
+            && self.name == other.name
+            && self.parameters == other.parameters
+            && self.rest == other.rest
+            && self.body == other.body
+    }
 }
 
 impl Function {
-    pub fn new(name: Token, parameters: &[Token], body: Stmt) -> Self {
+    pub fn new(name: Token, parameters: &[(Token, Option<Expr>)], rest: Option<Token>, body: Stmt) -> Self {
         Function {
             name,
             parameters: parameters.to_vec(),
+            rest,
             body,
+            closure: None,
         }
     }
 
+    /// Run the body in a scope nested in `environment` rather than in the caller's.
+    pub fn with_closure(mut self, environment: Rc<RefCell<Environment>>) -> Self {
+        self.closure = Some(environment);
+        self
+    }
+
     pub fn name(&self) -> String {
-        self.name.lexeme.clone()
+        self.name.lexeme.to_string()
+    }
+
+    /// Create the environment for a call, binding each parameter to its argument.
+    fn bind(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Literal>,
+    ) -> Result<Environment, RuntimeException> {
+        let mut arguments = arguments;
+
+        // arguments beyond the declared parameters are collected by the rest parameter.
+        let surplus = if arguments.len() > self.parameters.len() {
+            arguments.split_off(self.parameters.len())
+        } else {
+            Vec::new()
+        };
+
+        // missing trailing arguments take their default values, evaluated in the call environment.
+        for (_, default) in self.parameters.iter().skip(arguments.len()) {
+            match default {
+                Some(default) => arguments.push(interpreter.evaluate(default)?),
+                None => arguments.push(Literal::Null),
+            }
+        }
+
+        let enclosing = self.closure.clone().unwrap_or_else(|| interpreter.environment.clone());
+        let mut environment = Environment::new(Some(enclosing));
+
+        for ((parameter, _), argument) in self.parameters.iter().zip(arguments) {
+            environment.define(parameter.lexeme.to_string(), argument);
+        }
+
+        if let Some(rest) = &self.rest {
+            environment.define(rest.lexeme.to_string(), Literal::array(surplus));
+        }
+
+        Ok(environment)
+    }
+
+    /// Place keyword arguments into the slots of the parameters they name, producing a
+    /// purely positional argument list. Unfilled parameters take their default values.
+    pub fn arrange_arguments(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        positional: Vec<Literal>,
+        keywords: Vec<(Token, Literal)>,
+    ) -> Result<Vec<Literal>, RuntimeException> {
+        let mut positional = positional;
+        let surplus = if positional.len() > self.parameters.len() {
+            positional.split_off(self.parameters.len())
+        } else {
+            Vec::new()
+        };
+
+        let mut slots: Vec<Option<Literal>> = positional.into_iter().map(Some).collect();
+        slots.resize(self.parameters.len(), None);
+
+        for (name, value) in keywords {
+            let index = self
+                .parameters
+                .iter()
+                .position(|(parameter, _)| parameter.lexeme == name.lexeme);
+
+            match index {
+                Some(index) if slots[index].is_some() => {
+                    let message = format!("Argument '{}' given more than once.", name.lexeme);
+                    return Err(RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::ArityMismatch, name, message)));
+                }
+                Some(index) => slots[index] = Some(value),
+                None => {
+                    let message = format!("Unknown keyword argument '{}'.", name.lexeme);
+                    return Err(RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::ArityMismatch, name, message)));
+                }
+            }
+        }
+
+        let mut arguments = Vec::new();
+
+        for ((parameter, default), slot) in self.parameters.iter().zip(slots) {
+            match (slot, default) {
+                (Some(value), _) => arguments.push(value),
+                (None, Some(default)) => arguments.push(interpreter.evaluate(default)?),
+                (None, None) => {
+                    return Err(RuntimeException::Error(RuntimeError::new(
+                        RuntimeErrorKind::ArityMismatch,
+                        paren.clone(),
+                        format!("Missing argument for parameter '{}'.", parameter.lexeme),
+                    )));
+                }
+            }
+        }
+
+        arguments.extend(surplus);
+
+        Ok(arguments)
     }
 }
 
 impl Callable for Function {
     fn arity(&self) -> usize {
-        self.parameters.len()
+        self.parameters
+            .iter()
+            .filter(|(_, default)| default.is_none())
+            .count()
     }
 
-    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Literal>) -> Literal {
-        let mut environment = Environment::new(Some(interpreter.environment.clone()));
-
-        
-        for i in 0..self.parameters.len() {
-            environment.define(self.parameters[i].lexeme.clone(), arguments[i].clone());
+    fn max_arity(&self) -> usize {
+        if self.rest.is_some() {
+            usize::MAX
+        } else {
+            self.parameters.len()
         }
-        
-        let result = interpreter.execute_block(
-            self.body.get_block_body().unwrap(), 
-            environment.clone()
-        );
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeException> {
+        let mut arguments = arguments;
+
+        // the parser always gives a function a block, but one built some other way may not have it
+        let Some(body) = self.body.get_block_body() else {
+            let message = format!("Function '{}' has no body to run.", self.name.lexeme);
+            return Err(RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::NotCallable, self.name.clone(), message)));
+        };
 
-        match result {
-            Err(RuntimeException::Return(value)) => {
-                interpreter.environment = environment.get_enclosing_environment().unwrap();
+        loop {
+            let environment = self.bind(interpreter, arguments)?;
+            let result = interpreter.execute_body(body, environment);
 
-                value.value
-            },
-            _ => Literal::Null,
+            match result {
+                Err(RuntimeException::Return(value)) => return Ok(value.value),
+                // `return f(...)` inside `f` itself: run the body again with the new arguments
+                // rather than recursing, so tail recursion runs in constant stack space.
+                Err(RuntimeException::TailCall(next)) => arguments = next,
+                Err(error) => return Err(error),
+                Ok(value) => return Ok(value),
+            }
         }
     }
 }
@@ -0,0 +1,49 @@
+use std::{collections::HashMap, num::NonZeroU32, rc::Rc};
+
+/// A small handle to a string stored once in the interner.
+/// Two symbols are equal exactly when their strings are equal.
+/// It holds the string's index plus one, so an `Option<Symbol>` takes no extra space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(NonZeroU32);
+
+/// Stores each distinct identifier and keyword once, so a name that appears
+/// many times in a source file shares a single allocation. Each lexer has its own,
+/// so the strings are freed along with it.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    symbols: HashMap<Rc<str>, Symbol>,
+    strings: Vec<Rc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the symbol for a string, storing the string if it hasn't been seen before.
+    pub fn intern(&mut self, text: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(text) {
+            return *symbol;
+        }
+
+        let symbol = Symbol(NonZeroU32::MIN.saturating_add(self.strings.len() as u32));
+        let text: Rc<str> = Rc::from(text);
+        self.strings.push(text.clone());
+        self.symbols.insert(text, symbol);
+        symbol
+    }
+
+    /// Get the string a symbol stands for.
+    pub fn lookup(&self, symbol: Symbol) -> Rc<str> {
+        self.strings[symbol.0.get() as usize - 1].clone()
+    }
+
+    /// The number of distinct strings stored.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
@@ -1,75 +1,275 @@
+use std::cell::{Ref, RefCell};
+use std::rc::Rc;
+
 use crate::{
     callable::Callable,
     environment::Environment,
     function::Function,
     lexer::{Token, TokenType},
     literal::Literal,
+    native,
+    ordered_map::OrderedMap,
     r#return::Return,
-    stmt::{Expr, Stmt},
+    roz,
+    stmt::{Expr, LambdaDecl, Stmt},
 };
 
 #[derive(Debug)]
 pub enum RuntimeException {
     Error(RuntimeError),
     Return(Return),
+    Break(Option<String>),      // label, caught by the loop with that label or else the innermost one
+    Continue(Option<String>),   // label, caught like `Break`
+    TailCall(Vec<Literal>),     // arguments of a call a function makes to itself in tail position
 }
 
 #[derive(Debug)]
 pub struct RuntimeError {
-    pub token: Token,
+    pub kind: RuntimeErrorKind,
+    pub token: Box<Token>,  // boxed to keep `RuntimeException`, returned from every evaluation, small
     pub message: String,
+    pub trace: Vec<Frame>,  // call stack at the point of the error, innermost call last
+}
+
+/// What went wrong in a `RuntimeError`, for code that needs to tell errors apart
+/// without matching on their messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    TypeMismatch,       // an operand or value of the wrong type
+    UndefinedVariable,
+    AssignToConstant,
+    UndefinedKey,       // a map has no entry for the key
+    DivisionByZero,
+    ArityMismatch,      // wrong number of arguments, or a bad keyword argument
+    IndexOutOfBounds,
+    LengthMismatch,     // an array destructured into the wrong number of names
+    NotCallable,
+    InvalidArgument,    // rejected by a native function
+    AssertionFailed,
+    StackOverflow,      // calls nested deeper than `MAX_DEPTH` allows
+}
+
+impl RuntimeErrorKind {
+    pub fn to_string(&self) -> String {
+        match self {
+            RuntimeErrorKind::TypeMismatch => "type_mismatch".to_string(),
+            RuntimeErrorKind::UndefinedVariable => "undefined_variable".to_string(),
+            RuntimeErrorKind::AssignToConstant => "assign_to_constant".to_string(),
+            RuntimeErrorKind::UndefinedKey => "undefined_key".to_string(),
+            RuntimeErrorKind::DivisionByZero => "division_by_zero".to_string(),
+            RuntimeErrorKind::ArityMismatch => "arity_mismatch".to_string(),
+            RuntimeErrorKind::IndexOutOfBounds => "index_out_of_bounds".to_string(),
+            RuntimeErrorKind::LengthMismatch => "length_mismatch".to_string(),
+            RuntimeErrorKind::NotCallable => "not_callable".to_string(),
+            RuntimeErrorKind::InvalidArgument => "invalid_argument".to_string(),
+            RuntimeErrorKind::AssertionFailed => "assertion_failed".to_string(),
+            RuntimeErrorKind::StackOverflow => "stack_overflow".to_string(),
+        }
+    }
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind, token: Token, message: String) -> Self {
+        RuntimeError {
+            kind,
+            token: Box::new(token),
+            message,
+            trace: Vec::new(),
+        }
+    }
 }
 
+/// A function call in progress, recorded for tracebacks.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: String,
+    pub line: usize,    // line of the call site
+    pub function: Option<Rc<Function>>, // the function being run, if it is one declared in roz
+}
+
+/// How many expressions and statements may be evaluated inside one another before a call
+/// is refused. The parser already bounds the tree, so only calls can go past it, and
+/// refusing them here keeps runaway recursion within `roz::STACK_SIZE`.
+const MAX_DEPTH: usize = 20_000;
+
 pub struct Interpreter {
-    pub globals: Environment,
-    pub environment: Environment,
+    pub globals: Rc<RefCell<Environment>>,
+    pub environment: Rc<RefCell<Environment>>,
+    call_stack: Vec<Frame>,
+    depth: usize,       // expressions and statements being evaluated inside one another
+    args: Vec<String>,  // the script path and the arguments after it, as seen by `args()`
+    strict_truthiness: bool,    // whether zero and empty strings, arrays and maps are false
+    boolean_logical: bool,      // whether `and` and `or` give a bool rather than an operand
+    implicit_return: bool,      // whether a function gives the value of its last expression statement
+    step_hook: Option<Box<StepHook>>,
+    test_mode: bool,    // whether a failed `assert` is counted rather than stopping the program
+    assertions: (usize, usize), // passed, failed
+    continue_on_error: bool,    // whether a runtime error is reported and the next top-level statement run
+}
+
+/// Called before each statement with its line and the scope it runs in. See `set_step_hook`.
+pub type StepHook = dyn FnMut(usize, &Environment);
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        let interpreter = Self::sandboxed();
+        native::define_system_natives(&mut interpreter.globals.borrow_mut());
+        interpreter
+    }
+
+    /// An interpreter without the natives that reach outside of it, like file access,
+    /// for running code that shouldn't touch the system.
+    pub fn sandboxed() -> Self {
+        let mut globals = Environment::new(None);
+        native::define_natives(&mut globals);
+        let globals = Rc::new(RefCell::new(globals));
+
         Interpreter {
-            globals: Environment::new(None),
-            environment: Environment::new(None),
+            globals: globals.clone(),
+            environment: globals,
+            call_stack: Vec::new(),
+            depth: 0,
+            args: Vec::new(),
+            strict_truthiness: false,
+            boolean_logical: false,
+            implicit_return: false,
+            step_hook: None,
+            test_mode: false,
+            assertions: (0, 0),
+            continue_on_error: false,
+        }
+    }
+
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn set_strict_truthiness(&mut self, strict_truthiness: bool) {
+        self.strict_truthiness = strict_truthiness;
+    }
+
+    /// When enabled, `and` and `or` give `true` or `false` by the truthiness of the operand
+    /// that decided them, so `1 and 2` is `true` rather than `2`. They still short-circuit.
+    pub fn set_boolean_logical(&mut self, boolean_logical: bool) {
+        self.boolean_logical = boolean_logical;
+    }
+
+    /// When enabled, a function body that ends with an expression statement and finishes
+    /// without a `return` gives the value of that expression instead of nil.
+    pub fn set_implicit_return(&mut self, implicit_return: bool) {
+        self.implicit_return = implicit_return;
+    }
+
+    /// When enabled, `interpret` reports a runtime error and carries on with the next
+    /// top-level statement instead of stopping, as suits a REPL.
+    pub fn set_continue_on_error(&mut self, continue_on_error: bool) {
+        self.continue_on_error = continue_on_error;
+    }
+
+    /// In test mode a failed `assert` is reported and counted, and the program goes on.
+    pub fn set_test_mode(&mut self, test_mode: bool) {
+        self.test_mode = test_mode;
+    }
+
+    pub fn test_mode(&self) -> bool {
+        self.test_mode
+    }
+
+    pub fn record_assertion(&mut self, passed: bool) {
+        if passed {
+            self.assertions.0 += 1;
+        } else {
+            self.assertions.1 += 1;
         }
     }
 
-    fn evaluate(&mut self, expr: &Expr) -> Result<Literal, RuntimeException> {
-        self.walk_expr(expr)
+    /// How many assertions have passed and failed so far.
+    pub fn assertions(&self) -> (usize, usize) {
+        self.assertions
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Have `hook` called before each statement runs, with the line it starts on and the
+    /// scope it runs in, e.g. to trace execution or stop at breakpoints. Statements with no
+    /// position to report, like `1;`, are run without calling it.
+    pub fn set_step_hook(&mut self, hook: impl FnMut(usize, &Environment) + 'static) {
+        self.step_hook = Some(Box::new(hook));
+    }
+
+    /// The scope the next statement will run in, for tools that show variables as a program runs.
+    pub fn current_scope(&self) -> Ref<'_, Environment> {
+        self.environment.borrow()
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Literal, RuntimeException> {
+        self.depth += 1;
+        let result = self.walk_expr(expr);
+        self.depth -= 1;
+        result
     }
 
-    pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeException> {
+    /// Execute the statements in order, returning the value of the last one
+    /// when it is an expression statement and `Literal::Null` otherwise.
+    pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<Literal, RuntimeException> {
+        let mut value = Literal::Null;
+
         for stmt in stmts {
-            self.execute(stmt)?;
+            match self.execute(stmt) {
+                Ok(result) => value = result,
+                Err(RuntimeException::Error(error)) if self.continue_on_error => {
+                    roz::runtime_error(error);
+                }
+                // including a top-level `return` that the resolver didn't get to reject,
+                // which still ends the program
+                Err(exception) => return Err(exception),
+            }
         }
-        Ok(())
+
+        Ok(value)
     }
 
-    fn execute(&mut self, stmt: &Stmt) -> Result<(), RuntimeException> {
-        self.walk_stmt(stmt)?;
-        Ok(())
+    fn execute(&mut self, stmt: &Stmt) -> Result<Literal, RuntimeException> {
+        if let (Some(hook), Some(line)) = (&mut self.step_hook, stmt.line()) {
+            hook(line, &self.environment.borrow());
+        }
+
+        self.depth += 1;
+        let result = self.walk_stmt(stmt);
+        self.depth -= 1;
+        result
     }
 
-    fn is_true(&self, value: &Literal) -> bool {
+    pub fn is_true(&self, value: &Literal) -> bool {
         match value {
             Literal::Null => false,
             Literal::Bool(x) => *x,
+            Literal::Number(x) if self.strict_truthiness => *x != 0.0,
+            Literal::String(x) if self.strict_truthiness => !x.is_empty(),
+            Literal::Array(x) if self.strict_truthiness => !x.borrow().is_empty(),
+            Literal::Map(x) if self.strict_truthiness => !x.borrow().is_empty(),
             _ => true,
         }
     }
 
+    /// `nil` is equal to itself and nothing else.
     fn is_equal(&self, left: &Literal, right: &Literal) -> bool {
-        match (left, right) {
-            (Literal::Null, Literal::Null) => false,
-            (Literal::Null, _) => false,
-            (a, b) => a == b,
-        }
+        left == right
     }
 
     fn visit_literal_expr(&mut self, literal: &Literal) -> Result<Literal, RuntimeException> {
         Ok(literal.clone())
     }
 
-    fn visit_grouping_expr(&mut self, expr: &Box<Expr>) -> Result<Literal, RuntimeException> {
+    fn visit_grouping_expr(&mut self, expr: &Expr) -> Result<Literal, RuntimeException> {
         self.evaluate(expr)
     }
 
@@ -80,11 +280,15 @@ impl Interpreter {
     ) -> Result<Literal, RuntimeException> {
         let right = self.evaluate(expr)?;
 
-        self.check_number_operand(operator, &right)?;
-
         match operator.token_type {
-            TokenType::Minus => Ok((-right).unwrap()),
-            TokenType::Plus => Ok(right),
+            // only numbers can be negated, `!` is the way to flip a bool
+            TokenType::Minus => (-right).map_err(|message| {
+                RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::TypeMismatch, operator.clone(), message))
+            }),
+            TokenType::Plus => {
+                self.check_number_operand(operator, &right)?;
+                Ok(right)
+            }
             TokenType::Bang => Ok(Literal::Bool(!self.is_true(&right))),
             _ => Ok(Literal::Null),
         }
@@ -92,50 +296,158 @@ impl Interpreter {
 
     fn visit_call_expr(
         &mut self,
-        callee: &Expr,
+        callee: Literal,
         paren: Token,
         arguments: &[Expr],
+        keywords: &[(Token, Expr)],
     ) -> Result<Literal, RuntimeException> {
-        let callee = self.evaluate(callee)?;
-
         let mut arguments_ = Vec::new();
 
         for argument in arguments {
-            arguments_.push(self.evaluate(&argument)?)
+            arguments_.push(self.evaluate(argument)?)
+        }
+
+        let mut keywords_ = Vec::new();
+
+        for (name, argument) in keywords {
+            keywords_.push((name.clone(), self.evaluate(argument)?));
         }
 
         if callee.is_string() {
-            return Err(RuntimeException::Error(RuntimeError {
-                token: paren.clone(),
-                message: "Can only call functions and classes.".to_string(),
-            }));
+            return Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::NotCallable,
+                paren.clone(),
+                "Can only call functions and classes.".to_string(),
+            )));
         }
 
         match callee {
             Literal::Function(function) => {
-                if arguments_.len() != function.arity() {
-                    return Err(RuntimeException::Error(RuntimeError {
-                        token: paren,
-                        message: format!(
-                            "Expected {} arguments but got {}.",
-                            function.arity(),
-                            arguments_.len()
-                        ),
-                    }));
+                if !keywords_.is_empty() {
+                    arguments_ = function.arrange_arguments(self, &paren, arguments_, keywords_)?;
                 }
 
+                self.check_arity(function.as_ref(), &paren, arguments_.len())?;
+
                 self.environment
-                    .define(paren.lexeme, Literal::Function(function.clone()));
+                    .borrow_mut()
+                    .define(paren.lexeme.to_string(), Literal::Function(function.clone()));
+
+                self.call_with_frame(
+                    function.as_ref(),
+                    function.name(),
+                    Some(function.clone()),
+                    &paren,
+                    arguments_,
+                )
+            }
+            Literal::Native(native) => {
+                if let Some((name, _)) = keywords_.first() {
+                    return Err(RuntimeException::Error(RuntimeError::new(
+                        RuntimeErrorKind::ArityMismatch,
+                        name.clone(),
+                        format!("Native function '{}' doesn't take keyword arguments.", native.name()),
+                    )));
+                }
+
+                self.check_arity(native.as_ref(), &paren, arguments_.len())?;
+
+                self.call_with_frame(native.as_ref(), native.name(), None, &paren, arguments_)
+            }
+            Literal::Memo(memo) => {
+                if let Some((name, _)) = keywords_.first() {
+                    return Err(RuntimeException::Error(RuntimeError::new(
+                        RuntimeErrorKind::ArityMismatch,
+                        name.clone(),
+                        format!("Memoized function '{}' doesn't take keyword arguments.", memo.name()),
+                    )));
+                }
+
+                self.check_arity(memo.as_ref(), &paren, arguments_.len())?;
+
+                self.call_with_frame(memo.as_ref(), memo.name(), None, &paren, arguments_)
+            }
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::NotCallable,
+                paren,
+                "Couldn't execute function.".to_string(),
+            ))),
+        }
+    }
+
+    /// Call a function value with already evaluated positional arguments, as natives
+    /// that take a callback do.
+    pub fn call_value(
+        &mut self,
+        callee: &Literal,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeException> {
+        match callee {
+            Literal::Function(function) => {
+                self.check_arity(function.as_ref(), paren, arguments.len())?;
+                self.call_with_frame(
+                    function.as_ref(),
+                    function.name(),
+                    Some(function.clone()),
+                    paren,
+                    arguments,
+                )
+            }
+            Literal::Native(native) => {
+                self.check_arity(native.as_ref(), paren, arguments.len())?;
+                self.call_with_frame(native.as_ref(), native.name(), None, paren, arguments)
+            }
+            Literal::Memo(memo) => {
+                self.check_arity(memo.as_ref(), paren, arguments.len())?;
+                self.call_with_frame(memo.as_ref(), memo.name(), None, paren, arguments)
+            }
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::NotCallable,
+                paren.clone(),
+                "Can only call functions and classes.".to_string(),
+            ))),
+        }
+    }
 
-                Ok(function.call(self, arguments_))
+    /// Call a callable with a frame for it pushed on the call stack. The first call
+    /// boundary an error passes through records the whole stack into the error.
+    fn call_with_frame(
+        &mut self,
+        callable: &dyn Callable,
+        name: String,
+        function: Option<Rc<Function>>,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeException> {
+        if self.depth > MAX_DEPTH {
+            return Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::StackOverflow,
+                paren.clone(),
+                format!("Stack overflow calling '{}'.", name),
+            )));
+        }
+
+        self.call_stack.push(Frame {
+            name,
+            line: paren.line,
+            function,
+        });
+
+        let mut result = callable.call(self, paren, arguments);
+
+        if let Err(RuntimeException::Error(error)) = &mut result {
+            if error.trace.is_empty() {
+                error.trace = self.call_stack.clone();
             }
-            _ => Err(RuntimeException::Error(RuntimeError {
-                token: paren,
-                message: "Couldn't execute function.".to_string(),
-            })),
         }
+
+        self.call_stack.pop();
+        result
     }
 
+    /// `and` and `or` short-circuit: when the left operand alone decides the result it is
+    /// returned as is, and the right operand is never evaluated, side effects included.
     fn visit_logical_expr(
         &mut self,
         left: &Expr,
@@ -144,17 +456,19 @@ impl Interpreter {
     ) -> Result<Literal, RuntimeException> {
         let left = self.evaluate(left)?;
 
-        if operator.token_type == TokenType::And {
-            if !self.is_true(&left) {
-                return Ok(left);
-            }
+        let decided = if operator.token_type == TokenType::And {
+            !self.is_true(&left)
         } else {
-            if self.is_true(&left) {
-                return Ok(left);
-            }
-        }
+            self.is_true(&left)
+        };
+
+        let value = if decided { left } else { self.evaluate(right)? };
 
-        self.evaluate(right)
+        if self.boolean_logical {
+            Ok(Literal::Bool(self.is_true(&value)))
+        } else {
+            Ok(value)
+        }
     }
 
     fn visit_binary_expr(
@@ -171,10 +485,9 @@ impl Interpreter {
                 self.check_number_operands(&left, operator, &right)?;
                 Ok((left - right).unwrap())
             }
-            TokenType::Plus => {
-                // self.check_number_operands(&left, operator, &right)?;
-                Ok((left + right).unwrap())
-            }
+            TokenType::Plus => (left + right).map_err(|message| {
+                RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::TypeMismatch, operator.clone(), message))
+            }),
             TokenType::Star => {
                 self.check_number_operands(&left, operator, &right)?;
                 Ok((left * right).unwrap())
@@ -183,23 +496,217 @@ impl Interpreter {
                 self.check_number_operands(&left, operator, &right)?;
                 Ok((left / right).unwrap())
             }
-            TokenType::Greater => Ok(Literal::Bool(left > right)),
-            TokenType::Less => Ok(Literal::Bool(left < right)),
-            TokenType::GreaterEqual => Ok(Literal::Bool(left >= right)),
-            TokenType::LessEqual => Ok(Literal::Bool(left <= right)),
+            TokenType::StarStar => {
+                self.check_number_operands(&left, operator, &right)?;
+                match (left, right) {
+                    (Literal::Number(x), Literal::Number(y)) => Ok(Literal::Number(x.powf(y))),
+                    _ => Ok(Literal::Null),
+                }
+            }
+            TokenType::Div => {
+                self.check_number_operands(&left, operator, &right)?;
+                left.floor_div(right).map_err(|message| {
+                    RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::DivisionByZero, operator.clone(), message))
+                })
+            }
+            TokenType::Greater | TokenType::Less | TokenType::GreaterEqual | TokenType::LessEqual => {
+                self.check_comparable_operands(&left, operator, &right)?;
+                match operator.token_type {
+                    TokenType::Greater => Ok(Literal::Bool(left > right)),
+                    TokenType::Less => Ok(Literal::Bool(left < right)),
+                    TokenType::GreaterEqual => Ok(Literal::Bool(left >= right)),
+                    _ => Ok(Literal::Bool(left <= right)),
+                }
+            }
             TokenType::EqualEqual => Ok(Literal::Bool(self.is_equal(&left, &right))),
             TokenType::BangEqual => Ok(Literal::Bool(!self.is_equal(&left, &right))),
             _ => Ok(Literal::Null),
         }
     }
 
+    fn visit_if_expr(
+        &mut self,
+        condition: &Expr,
+        then_expr: &Expr,
+        else_expr: &Expr,
+    ) -> Result<Literal, RuntimeException> {
+        let cond_eval_result = self.evaluate(condition)?;
+
+        if self.is_true(&cond_eval_result) {
+            self.evaluate(then_expr)
+        } else {
+            self.evaluate(else_expr)
+        }
+    }
+
+    /// Unlike a declared function, which sees the scope it is called from, an anonymous one
+    /// keeps the scope it was made in, so it can be returned and still use the variables
+    /// around it. Kept out of `visit_expr`, whose every nested call would otherwise make
+    /// room on the stack for building a function.
+    #[inline(never)]
+    fn visit_lambda_expr(&mut self, lambda: &LambdaDecl) -> Literal {
+        // anonymous functions go by this name in tracebacks and when printed
+        let mut name = lambda.keyword.clone();
+        name.lexeme = Rc::from("anonymous");
+
+        let function = Function::new(name, &lambda.parameters, lambda.rest.clone(), lambda.body.clone())
+            .with_closure(self.environment.clone());
+        Literal::Function(Rc::new(function))
+    }
+
+    /// Strings are indexed by character rather than by byte, so `s[i]` never splits one.
+    fn visit_index_expr(
+        &mut self,
+        object: Literal,
+        bracket: &Token,
+        index: &Expr,
+    ) -> Result<Literal, RuntimeException> {
+        let index = self.evaluate(index)?;
+
+        match object {
+            Literal::Array(elements) => {
+                let elements = elements.borrow();
+                let position = self.position(bracket, &index, elements.len())?;
+                Ok(elements[position].clone())
+            }
+            Literal::String(string) => {
+                let chars: Vec<char> = string.chars().collect();
+                let position = self.position(bracket, &index, chars.len())?;
+                Ok(Literal::String(chars[position].to_string()))
+            }
+            Literal::Map(entries) => {
+                let key = self.map_key(bracket, index)?;
+                match entries.borrow().get(&key) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(RuntimeException::Error(RuntimeError::new(
+                        RuntimeErrorKind::UndefinedKey,
+                        bracket.clone(),
+                        format!("Undefined key '{}'.", key),
+                    ))),
+                }
+            }
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                bracket.clone(),
+                format!("Can't index a value of type {}.", object.literal_type()),
+            ))),
+        }
+    }
+
+    fn visit_slice_expr(
+        &mut self,
+        object: &Expr,
+        bracket: &Token,
+        start: &Expr,
+        end: &Expr,
+    ) -> Result<Literal, RuntimeException> {
+        let object = self.evaluate(object)?;
+        let start = self.evaluate(start)?;
+        let end = self.evaluate(end)?;
+
+        match object {
+            Literal::Array(elements) => {
+                let elements = elements.borrow();
+                let (start, end) = self.bounds(bracket, &start, &end, elements.len())?;
+                Ok(Literal::array(elements[start..end].to_vec()))
+            }
+            Literal::String(string) => {
+                let chars: Vec<char> = string.chars().collect();
+                let (start, end) = self.bounds(bracket, &start, &end, chars.len())?;
+                Ok(Literal::String(chars[start..end].iter().collect()))
+            }
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                bracket.clone(),
+                format!("Can't slice a value of type {}.", object.literal_type()),
+            ))),
+        }
+    }
+
+    /// `m.key` reads the entry of the map `m` under "key".
+    fn visit_get_expr(&mut self, object: Literal, name: &Token) -> Result<Literal, RuntimeException> {
+        match object {
+            Literal::Map(entries) => match entries.borrow().get(&name.lexeme) {
+                Some(value) => Ok(value.clone()),
+                None => Err(RuntimeException::Error(RuntimeError::new(
+                    RuntimeErrorKind::UndefinedKey,
+                    name.clone(),
+                    format!("Undefined key '{}'.", name.lexeme),
+                ))),
+            },
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                name.clone(),
+                format!(
+                    "Can't read property '{}' of a value of type {}.",
+                    name.lexeme,
+                    object.literal_type()
+                ),
+            ))),
+        }
+    }
+
+    /// Evaluate a chain of property accesses, indexing and calls, like `a?.b[0].c()`.
+    /// `None` means a `?.` met a nil receiver, which skips the rest of the chain.
+    fn evaluate_chain(&mut self, expr: &Expr) -> Result<Option<Literal>, RuntimeException> {
+        match expr {
+            Expr::Get(object, name, safe) => match self.evaluate_chain(object)? {
+                None => Ok(None),
+                Some(Literal::Null) if *safe => Ok(None),
+                Some(object) => self.visit_get_expr(object, name).map(Some),
+            },
+            Expr::Index(object, bracket, index) => match self.evaluate_chain(object)? {
+                None => Ok(None),
+                Some(object) => self.visit_index_expr(object, bracket, index).map(Some),
+            },
+            Expr::Call(callee, paren, arguments, keywords) => match self.evaluate_chain(callee)? {
+                None => Ok(None),
+                Some(callee) => {
+                    self.visit_call_expr(callee, paren.clone(), arguments, keywords).map(Some)
+                }
+            },
+            _ => self.evaluate(expr).map(Some),
+        }
+    }
+
+    fn visit_set_index_expr(
+        &mut self,
+        object: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<Literal, RuntimeException> {
+        let object = self.evaluate(object)?;
+        let index = self.evaluate(index)?;
+        let value = self.evaluate(value)?;
+
+        match object {
+            Literal::Array(elements) => {
+                let position = self.position(bracket, &index, elements.borrow().len())?;
+                elements.borrow_mut()[position] = value.clone();
+            }
+            Literal::Map(entries) => {
+                let key = self.map_key(bracket, index)?;
+                entries.borrow_mut().insert(key, value.clone());
+            }
+            _ => {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    RuntimeErrorKind::TypeMismatch,
+                    bracket.clone(),
+                    format!("Can't assign to an index of a value of type {}.", object.literal_type()),
+                )))
+            }
+        }
+
+        Ok(value)
+    }
+
     fn visit_variable_expr(&mut self, name: &Token) -> Result<Literal, RuntimeException> {
-        self.environment.get(name.clone())
+        self.environment.borrow().get(name.clone())
     }
 
-    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<(), RuntimeException> {
-        self.evaluate(expr)?;
-        Ok(())
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<Literal, RuntimeException> {
+        self.evaluate(expr)
     }
 
     fn visit_print_stmt(&mut self, expr: &Expr) -> Result<(), RuntimeException> {
@@ -215,54 +722,245 @@ impl Interpreter {
             value = self.evaluate(initializer)?;
         }
 
-        self.environment.define(name.lexeme.clone(), value);
+        self.environment.borrow_mut().define(name.lexeme.to_string(), value);
+
+        Ok(())
+    }
+
+    /// Bind the value of the initializer unless it is nil, in which case run the else
+    /// branch instead, which the resolver has made sure leaves the enclosing code.
+    fn visit_let_else_stmt(
+        &mut self,
+        name: &Token,
+        initializer: &Expr,
+        else_branch: &Stmt,
+    ) -> Result<(), RuntimeException> {
+        match self.evaluate(initializer)? {
+            Literal::Null => self.execute(else_branch).map(|_| ()),
+            value => {
+                self.environment.borrow_mut().define(name.lexeme.to_string(), value);
+                Ok(())
+            }
+        }
+    }
+
+    fn visit_const_stmt(&mut self, name: &Token, initializer: &Expr) -> Result<(), RuntimeException> {
+        let value = self.evaluate(initializer)?;
+
+        self.environment.borrow_mut().define_constant(name.lexeme.to_string(), value);
+
+        Ok(())
+    }
+
+    fn visit_destructure_stmt(
+        &mut self,
+        bracket: &Token,
+        names: &[Token],
+        rest: &Option<Token>,
+        initializer: &Expr,
+    ) -> Result<(), RuntimeException> {
+        let elements = match self.evaluate(initializer)? {
+            Literal::Array(elements) => elements.borrow().clone(),
+            value => {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    RuntimeErrorKind::TypeMismatch,
+                    bracket.clone(),
+                    format!("Can only destructure an array, got '{}'.", value.literal_type()),
+                )));
+            }
+        };
+
+        let mismatched = if rest.is_some() {
+            elements.len() < names.len()
+        } else {
+            elements.len() != names.len()
+        };
+
+        if mismatched {
+            return Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::LengthMismatch,
+                bracket.clone(),
+                format!(
+                    "Expected {}{} elements to destructure but got {}.",
+                    if rest.is_some() { "at least " } else { "" },
+                    names.len(),
+                    elements.len()
+                ),
+            )));
+        }
+
+        let mut elements = elements;
+        let surplus = elements.split_off(names.len());
+
+        for (name, value) in names.iter().zip(elements) {
+            self.environment.borrow_mut().define(name.lexeme.to_string(), value);
+        }
+
+        if let Some(rest) = rest {
+            self.environment.borrow_mut().define(rest.lexeme.to_string(), Literal::array(surplus));
+        }
 
         Ok(())
     }
 
+    /// Run the branch of the first arm whose condition is true, or the else branch if
+    /// none is. Conditions after the one that is true aren't evaluated.
     fn visit_if_stmt(
         &mut self,
-        condition: &Expr,
-        then_stmt: &Stmt,
+        arms: &[(Expr, Stmt)],
         else_stmt: &Stmt,
     ) -> Result<(), RuntimeException> {
-        let cond_eval_result = self.evaluate(condition)?;
+        for (condition, branch) in arms {
+            let cond_eval_result = self.evaluate(condition)?;
 
-        if self.is_true(&cond_eval_result) {
-            self.execute(then_stmt)?;
-        } else if *else_stmt != Stmt::None {
+            if self.is_true(&cond_eval_result) {
+                self.execute(branch)?;
+                return Ok(());
+            }
+        }
+
+        if *else_stmt != Stmt::None {
             self.execute(else_stmt)?;
         }
 
         Ok(())
     }
 
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<(), RuntimeException> {
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Expr,
+        else_branch: &Stmt,
+        label: &Option<Token>,
+    ) -> Result<(), RuntimeException> {
         let mut cond_eval_result = self.evaluate(condition)?;
 
         while self.is_true(&cond_eval_result) {
-            self.execute(body)?;
+            if self.execute_loop_body(body, label)? {
+                return Ok(());
+            }
+
+            self.evaluate(increment)?;
             cond_eval_result = self.evaluate(condition)?;
         }
 
+        self.execute(else_branch)?;
+        Ok(())
+    }
+
+    /// Run one iteration of a loop body, returning whether a `break` ended the loop.
+    /// A `break` or `continue` aimed at an outer loop is passed on.
+    fn execute_loop_body(&mut self, body: &Stmt, label: &Option<Token>) -> Result<bool, RuntimeException> {
+        let targets_this_loop = |target: &Option<String>| match (target, label) {
+            (None, _) => true,
+            (Some(target), Some(label)) => **target == *label.lexeme,
+            (Some(_), None) => false,
+        };
+
+        match self.execute(body) {
+            Ok(_) => Ok(false),
+            Err(RuntimeException::Continue(target)) if targets_this_loop(&target) => Ok(false),
+            Err(RuntimeException::Break(target)) if targets_this_loop(&target) => Ok(true),
+            Err(exception) => Err(exception),
+        }
+    }
+
+    /// Run the body as many times as the count says, which is evaluated once, up front.
+    fn visit_repeat_stmt(
+        &mut self,
+        keyword: &Token,
+        count: &Expr,
+        body: &Stmt,
+        label: &Option<Token>,
+    ) -> Result<(), RuntimeException> {
+        let times = match self.evaluate(count)? {
+            Literal::Number(n) if n >= 0.0 && n.fract() == 0.0 => n as u64,
+            count @ Literal::Number(_) => {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    RuntimeErrorKind::InvalidArgument,
+                    keyword.clone(),
+                    format!("Repeat count must be a non-negative integer, got {}.", count.to_string()),
+                )));
+            }
+            value => {
+                return Err(RuntimeException::Error(RuntimeError::new(
+                    RuntimeErrorKind::TypeMismatch,
+                    keyword.clone(),
+                    format!("Repeat count must be a number, got '{}'.", value.literal_type()),
+                )));
+            }
+        };
+
+        for _ in 0..times {
+            if self.execute_loop_body(body, label)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_do_while_stmt(
+        &mut self,
+        body: &Stmt,
+        condition: &Expr,
+        label: &Option<Token>,
+    ) -> Result<(), RuntimeException> {
+        loop {
+            if self.execute_loop_body(body, label)? {
+                break;
+            }
+
+            let cond_eval_result = self.evaluate(condition)?;
+            if !self.is_true(&cond_eval_result) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_switch_stmt(
+        &mut self,
+        subject: &Expr,
+        cases: &[(Expr, Vec<Stmt>)],
+        default: &Option<Vec<Stmt>>,
+    ) -> Result<(), RuntimeException> {
+        let subject = self.evaluate(subject)?;
+
+        for (value, stmts) in cases {
+            let value = self.evaluate(value)?;
+
+            if self.is_equal(&subject, &value) {
+                return self.visit_block_stmt(stmts);
+            }
+        }
+
+        if let Some(stmts) = default {
+            self.visit_block_stmt(stmts)?;
+        }
+
         Ok(())
     }
 
     fn visit_block_stmt(&mut self, stmts: &[Stmt]) -> Result<(), RuntimeException> {
-        let env = self.environment.clone();
-        self.execute_block(&stmts, Environment::new(Some(env)))
+        let environment = Environment::new(Some(self.environment.clone()));
+        self.execute_block(stmts, environment)
     }
 
     fn visit_function_stmt(
         &mut self,
         name: &Token,
-        parameters: &[Token],
+        parameters: &[(Token, Option<Expr>)],
+        rest: &Option<Token>,
         body: Stmt,
     ) -> Result<(), RuntimeException> {
-        let function = Function::new(name.clone(), parameters, body);
+        let function = Function::new(name.clone(), parameters, rest.clone(), body);
 
         self.environment
-            .define(name.lexeme.clone(), Literal::Function(Box::new(function)));
+            .borrow_mut()
+            .define(name.lexeme.to_string(), Literal::Function(Rc::new(function)));
 
         Ok(())
     }
@@ -274,7 +972,22 @@ impl Interpreter {
     ) -> Result<(), RuntimeException> {
         let mut resulting_value = Literal::Null;
 
-        if *value != Expr::None {
+        if let Expr::Call(callee, paren, arguments, keywords) = value {
+            let callee = match self.evaluate_chain(callee)? {
+                Some(callee) => callee,
+                None => {
+                    return Err(RuntimeException::Return(Return {
+                        value: Literal::Null,
+                    }))
+                }
+            };
+
+            if let Some(arguments) = self.tail_call_arguments(&callee, paren, arguments, keywords)? {
+                return Err(RuntimeException::TailCall(arguments));
+            }
+
+            resulting_value = self.visit_call_expr(callee, paren.clone(), arguments, keywords)?;
+        } else if *value != Expr::None {
             resulting_value = self.evaluate(value)?;
         }
 
@@ -283,6 +996,46 @@ impl Interpreter {
         }))
     }
 
+    /// When `return callee(...)` calls the function that is running, evaluate the arguments
+    /// so that `Function::call` can run the body again with them instead of recursing.
+    /// This keeps tail recursion from using up the stack, at the cost of showing a single
+    /// frame for the whole recursion in a traceback.
+    fn tail_call_arguments(
+        &mut self,
+        callee: &Literal,
+        paren: &Token,
+        arguments: &[Expr],
+        keywords: &[(Token, Expr)],
+    ) -> Result<Option<Vec<Literal>>, RuntimeException> {
+        let function = match (callee, self.call_stack.last()) {
+            (
+                Literal::Function(function),
+                Some(Frame {
+                    function: Some(running),
+                    ..
+                }),
+            ) if Rc::ptr_eq(function, running) => function.clone(),
+            _ => return Ok(None),
+        };
+
+        let mut positional = Vec::new();
+        for argument in arguments {
+            positional.push(self.evaluate(argument)?);
+        }
+
+        if !keywords.is_empty() {
+            let mut named = Vec::new();
+            for (name, argument) in keywords {
+                named.push((name.clone(), self.evaluate(argument)?));
+            }
+            positional = function.arrange_arguments(self, paren, positional, named)?;
+        }
+
+        self.check_arity(function.as_ref(), paren, positional.len())?;
+
+        Ok(Some(positional))
+    }
+
     fn check_number_operand(
         &self,
         operator: &Token,
@@ -291,10 +1044,11 @@ impl Interpreter {
         if operand.is_double() {
             return Ok(());
         } else {
-            return Err(RuntimeException::Error(RuntimeError {
-                token: operator.clone(),
-                message: "Expected the operand to be a double.".to_string(),
-            }));
+            return Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                operator.clone(),
+                format!("Expected the operand to be a double, got '{}'.", operand.literal_type()),
+            )));
         }
     }
 
@@ -307,33 +1061,182 @@ impl Interpreter {
         if left.is_double() && right.is_double() {
             return Ok(());
         } else {
-            return Err(RuntimeException::Error(RuntimeError {
-                token: operator.clone(),
-                message: "Expected both operands to be double.".to_string(),
-            }));
+            return Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                operator.clone(),
+                "Expected both operands to be double.".to_string(),
+            )));
+        }
+    }
+
+    /// `<`, `>`, `<=` and `>=` compare two numbers, or two strings in lexicographic order.
+    fn check_comparable_operands(
+        &self,
+        left: &Literal,
+        operator: &Token,
+        right: &Literal,
+    ) -> Result<(), RuntimeException> {
+        match (left, right) {
+            (Literal::Number(_), Literal::Number(_)) | (Literal::String(_), Literal::String(_)) => Ok(()),
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                operator.clone(),
+                format!(
+                    "Can only compare two numbers or two strings, got '{}' and '{}'.",
+                    left.literal_type(),
+                    right.literal_type()
+                ),
+            ))),
         }
     }
 
+    fn map_key(&self, token: &Token, key: Literal) -> Result<String, RuntimeException> {
+        match key {
+            Literal::String(key) => Ok(key),
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                token.clone(),
+                format!("Map keys must be strings, not {}.", key.literal_type()),
+            ))),
+        }
+    }
+
+    /// Turn an index into a position in a sequence of `length` items.
+    /// A negative index counts back from the end, so `-1` is the last item.
+    fn position(&self, bracket: &Token, index: &Literal, length: usize) -> Result<usize, RuntimeException> {
+        let position = self.offset(bracket, index, length)?;
+
+        if (0..length as i64).contains(&position) {
+            return Ok(position as usize);
+        }
+
+        Err(RuntimeException::Error(RuntimeError::new(
+            RuntimeErrorKind::IndexOutOfBounds,
+            bracket.clone(),
+            format!("Index {} is out of range for length {}.", index.to_string(), length),
+        )))
+    }
+
+    /// Turn the bounds of a slice into a range of positions. A missing (nil) start is the
+    /// beginning and a missing end is the end; negative bounds count back from the end.
+    fn bounds(
+        &self,
+        bracket: &Token,
+        start: &Literal,
+        end: &Literal,
+        length: usize,
+    ) -> Result<(usize, usize), RuntimeException> {
+        let start_position = match start {
+            Literal::Null => 0,
+            _ => self.offset(bracket, start, length)?,
+        };
+        let end_position = match end {
+            Literal::Null => length as i64,
+            _ => self.offset(bracket, end, length)?,
+        };
+
+        if 0 <= start_position && start_position <= end_position && end_position <= length as i64 {
+            return Ok((start_position as usize, end_position as usize));
+        }
+
+        Err(RuntimeException::Error(RuntimeError::new(
+            RuntimeErrorKind::IndexOutOfBounds,
+            bracket.clone(),
+            format!(
+                "Slice {}..{} is out of range for length {}.",
+                start.to_string(),
+                end.to_string(),
+                length
+            ),
+        )))
+    }
+
+    /// The position an integer index refers to, which may still be out of range.
+    fn offset(&self, bracket: &Token, index: &Literal, length: usize) -> Result<i64, RuntimeException> {
+        match index {
+            Literal::Number(index) if index.fract() == 0.0 && *index < 0.0 => Ok(length as i64 + *index as i64),
+            Literal::Number(index) if index.fract() == 0.0 => Ok(*index as i64),
+            _ => Err(RuntimeException::Error(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch,
+                bracket.clone(),
+                "An index must be an integer.".to_string(),
+            ))),
+        }
+    }
+
+    fn check_arity(
+        &self,
+        callable: &dyn Callable,
+        paren: &Token,
+        count: usize,
+    ) -> Result<(), RuntimeException> {
+        let (min, max) = (callable.arity(), callable.max_arity());
+
+        if count >= min && count <= max {
+            return Ok(());
+        }
+
+        let message = if min == max {
+            format!("Expected {} arguments but got {}.", min, count)
+        } else if count < min {
+            format!("Expected at least {} arguments but got {}.", min, count)
+        } else {
+            format!("Expected at most {} arguments but got {}.", max, count)
+        };
+
+        Err(RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::ArityMismatch, paren.clone(), message)))
+    }
+
+    /// Run `stmts` in `environment`, then go back to the environment that was current before.
+    /// The result is captured before the environment is restored, so an error, `break` or
+    /// `return` from inside the block leaves the interpreter in the enclosing scope too,
+    /// and a REPL session carries on normally after a failed line.
     pub fn execute_block(
         &mut self,
         stmts: &[Stmt],
         environment: Environment,
     ) -> Result<(), RuntimeException> {
-        self.environment = environment;
-        for stmt in stmts {
-            self.execute(stmt)?;
-        }
+        let environment = Rc::new(RefCell::new(environment));
+        let enclosing = std::mem::replace(&mut self.environment, environment);
 
-        self.environment = self.environment.get_enclosing_environment().unwrap();
-        Ok(())
+        let result = stmts.iter().try_for_each(|stmt| self.execute(stmt).map(|_| ()));
+
+        self.environment = enclosing;
+        result
+    }
+
+    /// Run a function body in the environment of a call, giving the value it finishes
+    /// with when it doesn't `return`: nil, or with implicit returns, the value of a final
+    /// expression statement.
+    pub fn execute_body(
+        &mut self,
+        stmts: &[Stmt],
+        environment: Environment,
+    ) -> Result<Literal, RuntimeException> {
+        let environment = Rc::new(RefCell::new(environment));
+        let enclosing = std::mem::replace(&mut self.environment, environment);
+
+        let result = match stmts.split_last() {
+            Some((Stmt::Expression(value), init)) if self.implicit_return => init
+                .iter()
+                .try_for_each(|stmt| self.execute(stmt).map(|_| ()))
+                .and_then(|_| self.evaluate(value)),
+            _ => stmts
+                .iter()
+                .try_for_each(|stmt| self.execute(stmt).map(|_| ()))
+                .map(|_| Literal::Null),
+        };
+
+        self.environment = enclosing;
+        result
     }
 }
 
 pub trait Visitor {
     fn visit_expr(&mut self, expr: &Expr) -> Result<Literal, RuntimeException>;
     fn walk_expr(&mut self, expr: &Expr) -> Result<Literal, RuntimeException>;
-    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), RuntimeException>;
-    fn walk_stmt(&mut self, stmt: &Stmt) -> Result<(), RuntimeException>;
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<Literal, RuntimeException>;
+    fn walk_stmt(&mut self, stmt: &Stmt) -> Result<Literal, RuntimeException>;
 }
 
 impl Visitor for Interpreter {
@@ -347,11 +1250,41 @@ impl Visitor for Interpreter {
             Expr::Variable(name) => self.visit_variable_expr(name),
             Expr::Assign(name, rhs) => {
                 let value = self.evaluate(rhs)?;
-                self.environment.assign(name.clone(), value.clone())?;
+                self.environment.borrow_mut().assign(name.clone(), value.clone())?;
                 Ok(value)
             }
-            Expr::Call(callee, paren, arguments) => {
-                self.visit_call_expr(callee, paren.clone(), arguments)
+            Expr::Call(..) | Expr::Get(..) | Expr::Index(..) => {
+                Ok(self.evaluate_chain(expr)?.unwrap_or(Literal::Null))
+            }
+            Expr::Array(elements) => {
+                let mut values = Vec::new();
+
+                for element in elements {
+                    values.push(self.evaluate(element)?);
+                }
+
+                Ok(Literal::array(values))
+            }
+            Expr::Map(brace, entries) => {
+                let mut values = OrderedMap::new();
+
+                for (key, value) in entries {
+                    let key = self.evaluate(key)?;
+                    let key = self.map_key(brace, key)?;
+                    values.insert(key, self.evaluate(value)?);
+                }
+
+                Ok(Literal::map(values))
+            }
+            Expr::If(condition, then_expr, else_expr) => {
+                self.visit_if_expr(condition, then_expr, else_expr)
+            }
+            Expr::Lambda(lambda) => Ok(self.visit_lambda_expr(lambda)),
+            Expr::Slice(object, bracket, start, end) => {
+                self.visit_slice_expr(object, bracket, start, end)
+            }
+            Expr::SetIndex(object, bracket, index, value) => {
+                self.visit_set_index_expr(object, bracket, index, value)
             }
             Expr::None => Ok(Literal::Null),
         }
@@ -361,26 +1294,153 @@ impl Visitor for Interpreter {
         self.visit_expr(expr)
     }
 
-    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<(), RuntimeException> {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> Result<Literal, RuntimeException> {
         match stmt {
-            Stmt::Expression(expr) => self.visit_expr_stmt(expr),
-            Stmt::Print(expr) => self.visit_print_stmt(expr),
-            Stmt::If(condition, then_statement, else_statement) => {
-                self.visit_if_stmt(condition, then_statement, else_statement)
+            Stmt::Expression(expr) => return self.visit_expr_stmt(expr),
+            Stmt::Print(_, expr) => self.visit_print_stmt(expr),
+            Stmt::If(arms, else_statement) => self.visit_if_stmt(arms, else_statement),
+            Stmt::While(condition, body, increment, else_branch, label) => {
+                self.visit_while_stmt(condition, body, increment, else_branch, label)
             }
-            Stmt::While(condition, body) => self.visit_while_stmt(condition, body),
+            Stmt::DoWhile(body, condition, label) => self.visit_do_while_stmt(body, condition, label),
+            Stmt::Repeat(keyword, count, body, label) => self.visit_repeat_stmt(keyword, count, body, label),
+            Stmt::Switch(subject, cases, default) => self.visit_switch_stmt(subject, cases, default),
             Stmt::Var(name, initializer) => self.visit_var_stmt(name, initializer),
+            Stmt::LetElse(name, initializer, else_branch) => {
+                self.visit_let_else_stmt(name, initializer, else_branch)
+            }
+            Stmt::VarMulti(declarations) => {
+                for (name, initializer) in declarations {
+                    self.visit_var_stmt(name, initializer)?;
+                }
+                Ok(())
+            }
+            Stmt::Const(name, initializer) => self.visit_const_stmt(name, initializer),
+            Stmt::Destructure(bracket, names, rest, initializer) => {
+                self.visit_destructure_stmt(bracket, names, rest, initializer)
+            }
             Stmt::Block(stmts) => self.visit_block_stmt(stmts),
-            Stmt::Function(name, parameters, body) => {
-                self.visit_function_stmt(name, parameters, *body.clone())
+            Stmt::Function(name, parameters, rest, body) => {
+                self.visit_function_stmt(name, parameters, rest, *body.clone())
             }
             Stmt::Return(keyword, value) => self.visit_return_stmt(keyword, value),
+            Stmt::Break(_, label) => {
+                Err(RuntimeException::Break(label.as_ref().map(|label| label.lexeme.to_string())))
+            }
+            Stmt::Continue(_, label) => {
+                Err(RuntimeException::Continue(label.as_ref().map(|label| label.lexeme.to_string())))
+            }
             Stmt::None => Ok(()),
+        }?;
+
+        Ok(Literal::Null)
+    }
+
+    fn walk_stmt(&mut self, stmt: &Stmt) -> Result<Literal, RuntimeException> {
+        self.visit_stmt(stmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> Result<Literal, RuntimeException> {
+        let stmts = Parser::from_lexer(Lexer::new(source)).parse().expect("source should parse");
+        interpreter.interpret(&stmts)
+    }
+
+    #[test]
+    fn an_error_inside_a_block_leaves_the_enclosing_scope_current() {
+        let mut interpreter = Interpreter::sandboxed();
+        run(&mut interpreter, "let x = \"outer\";").unwrap();
+
+        assert!(run(&mut interpreter, "{ let x = \"inner\"; x + nil; }").is_err());
+        assert!(run(&mut interpreter, "fn f() { let x = \"inner\"; return x + nil; } f();").is_err());
+
+        assert_eq!(run(&mut interpreter, "x;").unwrap(), Literal::String("outer".to_string()));
+    }
+
+    #[test]
+    fn calling_a_function_without_a_block_body_is_an_error() {
+        let mut interpreter = Interpreter::sandboxed();
+        let name = Token::new(TokenType::Identifier, "f", Literal::Null, 1, 1, 0, 1);
+        let function = Function::new(name.clone(), &[], None, Stmt::None);
+
+        match function.call(&mut interpreter, &name, Vec::new()) {
+            Err(RuntimeException::Error(error)) => assert_eq!(error.kind, RuntimeErrorKind::NotCallable),
+            _ => panic!("expected a runtime error"),
         }
     }
 
-    fn walk_stmt(&mut self, stmt: &Stmt) -> Result<(), RuntimeException> {
-        self.visit_stmt(stmt)?;
-        Ok(())
+    #[test]
+    fn interpret_returns_the_value_of_the_last_expression_statement() {
+        let mut interpreter = Interpreter::sandboxed();
+
+        assert_eq!(run(&mut interpreter, "1; 2; 3;").unwrap(), Literal::Number(3.0));
+        assert_eq!(run(&mut interpreter, "1; let x = 2;").unwrap(), Literal::Null);
+    }
+
+    #[test]
+    fn a_snapshot_lists_a_shadowed_name_once_per_scope_innermost_first() {
+        let mut interpreter = Interpreter::sandboxed();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let snapshot = Rc::clone(&seen);
+        interpreter.set_step_hook(move |line, scope| {
+            if line == 5 {
+                *snapshot.borrow_mut() = scope.snapshot();
+            }
+        });
+
+        run(&mut interpreter, "let x = \"outer\";\n{\n    let x = \"inner\";\n    let y = 1;\n    y;\n}").unwrap();
+
+        let bindings: Vec<(String, Literal)> =
+            seen.take().into_iter().filter(|(name, _)| name == "x" || name == "y").collect();
+        assert_eq!(
+            bindings,
+            [
+                ("x".to_string(), Literal::String("inner".to_string())),
+                ("y".to_string(), Literal::Number(1.0)),
+                ("x".to_string(), Literal::String("outer".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_step_hook_is_called_before_each_statement_with_its_line() {
+        let mut interpreter = Interpreter::sandboxed();
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&lines);
+        interpreter.set_step_hook(move |line, _| seen.borrow_mut().push(line));
+
+        run(&mut interpreter, "let a = 1;\nlet b = 2;\nlet c = a + b;").unwrap();
+
+        assert_eq!(*lines.borrow(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn dividing_by_zero_is_a_division_by_zero_error() {
+        let mut interpreter = Interpreter::sandboxed();
+
+        match run(&mut interpreter, "7 div 0;") {
+            Err(RuntimeException::Error(error)) => assert_eq!(error.kind, RuntimeErrorKind::DivisionByZero),
+            _ => panic!("expected a runtime error"),
+        }
+    }
+
+    #[test]
+    fn a_snapshot_lists_names_in_the_order_they_were_defined() {
+        let mut interpreter = Interpreter::sandboxed();
+        run(&mut interpreter, "let zebra = 1; let apple = 2; let mango = 3; zebra = 4;").unwrap();
+
+        let names: Vec<String> = interpreter
+            .current_scope()
+            .snapshot()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| ["zebra", "apple", "mango"].contains(&name.as_str()))
+            .collect();
+        assert_eq!(names, ["zebra", "apple", "mango"]);
     }
 }
@@ -1,18 +1,23 @@
 use std::{
     collections::HashMap,
+    rc::Rc,
     sync::OnceLock
 };
 
 use crate::{
-    literal::Literal,
+    interner::Interner,
+    literal::{self, Literal},
     roz,
 };
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
     // single-character tokens
-    LeftParen, RightParen, LeftBrace, RightBrace,
-    Comma, Dot, Semicolon, Minus, Plus, Slash, Star,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
+    Comma, Dot, Semicolon, Colon, Minus, Plus, Slash, Star,
+
+    // multi-character punctuation
+    DotDot, DotDotDot, QuestionDot, StarStar, Pipe, FatArrow,
 
     // Operators
     Equal, EqualEqual, Bang, BangEqual,
@@ -22,8 +27,9 @@ pub enum TokenType {
     Identifier, String, Number,
 
     // reserved words
-    And, Or, Class, Super, This, If, Else, For, While,
-    False, True, Fn, Return, Print, Let, Nil, 
+    And, Or, Class, Super, This, If, Else, For, While, Do,
+    False, True, Fn, Return, Print, Let, Const, Nil,
+    Switch, Case, Default, Div, Break, Continue, Repeat,
 
     EOF
 }
@@ -35,9 +41,18 @@ impl TokenType {
             Self::RightParen => "RIGHT_PAREN".to_string(),
             Self::LeftBrace => "LEFT_BRACE".to_string(),
             Self::RightBrace => "RIGHT_BRACE".to_string(),
+            Self::LeftBracket => "LEFT_BRACKET".to_string(),
+            Self::RightBracket => "RIGHT_BRACKET".to_string(),
             Self::Comma => "COMMA".to_string(),
             Self::Dot => "DOT".to_string(),
+            Self::DotDot => "DOTDOT".to_string(),
+            Self::DotDotDot => "DOTDOTDOT".to_string(),
+            Self::QuestionDot => "QUESTION_DOT".to_string(),
+            Self::StarStar => "STAR_STAR".to_string(),
+            Self::Pipe => "PIPE".to_string(),
+            Self::FatArrow => "FAT_ARROW".to_string(),
             Self::Semicolon => "SEMICOLON".to_string(),
+            Self::Colon => "COLON".to_string(),
             Self::Minus => "MINUS".to_string(),
             Self::Plus => "PLUS".to_string(),
             Self::Slash => "SLASH".to_string(),
@@ -62,13 +77,22 @@ impl TokenType {
             Self::Else => "ELSE".to_string(),
             Self::For => "FOR".to_string(),
             Self::While => "WHILE".to_string(),
+            Self::Do => "DO".to_string(),
             Self::False => "FALSE".to_string(),
             Self::True => "TRUE".to_string(),
             Self::Fn => "FN".to_string(),
             Self::Return => "RETURN".to_string(),
             Self::Print => "PRINT".to_string(),
             Self::Let => "LET".to_string(),
+            Self::Const => "CONST".to_string(),
             Self::Nil => "NIL".to_string(),
+            Self::Switch => "SWITCH".to_string(),
+            Self::Case => "CASE".to_string(),
+            Self::Default => "DEFAULT".to_string(),
+            Self::Div => "DIV".to_string(),
+            Self::Break => "BREAK".to_string(),
+            Self::Continue => "CONTINUE".to_string(),
+            Self::Repeat => "REPEAT".to_string(),
             Self::EOF => "EOF".to_string()
         }
     }
@@ -77,18 +101,46 @@ impl TokenType {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Rc<str>,
     pub literal: Literal,
-    pub line: usize
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,   // byte offsets of the lexeme in the source, which is `source[start..end]`
+    pub end: usize,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Literal, line: usize) -> Self {
+    pub fn new(
+        token_type: TokenType,
+        lexeme: &str,
+        literal: Literal,
+        line: usize,
+        column: usize,
+        start: usize,
+        end: usize,
+    ) -> Self {
         Token {
             token_type,
-            lexeme,
+            lexeme: Rc::from(lexeme),
             literal,
-            line
+            line,
+            column,
+            start,
+            end,
+        }
+    }
+
+    /// Create a token whose lexeme is shared with every other token spelled the same way,
+    /// as handed out by an `Interner`.
+    pub fn interned(token_type: TokenType, lexeme: Rc<str>, line: usize, column: usize, start: usize, end: usize) -> Self {
+        Token {
+            token_type,
+            lexeme,
+            literal: Literal::Null,
+            line,
+            column,
+            start,
+            end,
         }
     }
 
@@ -111,23 +163,41 @@ fn keywords() -> &'static HashMap<&'static str, TokenType> {
             ("else",    TokenType::Else),
             ("for",     TokenType::For),
             ("while",   TokenType::While),
+            ("do",      TokenType::Do),
             ("false",   TokenType::False),
             ("true",    TokenType::True),
             ("fn",      TokenType::Fn),
             ("return",  TokenType::Return),
             ("print",   TokenType::Print),
             ("let",     TokenType::Let),
-            ("nil",     TokenType::Nil)
+            ("const",   TokenType::Const),
+            ("nil",     TokenType::Nil),
+            ("switch",  TokenType::Switch),
+            ("case",    TokenType::Case),
+            ("default", TokenType::Default),
+            ("div",     TokenType::Div),
+            ("break",   TokenType::Break),
+            ("continue", TokenType::Continue),
+            ("repeat",  TokenType::Repeat),
         ])
     })
 }
 
+#[derive(Clone)]
 pub struct Lexer {
     source: String,
     pub tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: usize,
+    column: usize,              // 1-based column of the character at `current`, kept up as it advances
+    start_line: usize,          // line and column of the character at `start`, where the
+    start_column: usize,        // token being scanned begins even if it spans lines
+    scanned: Option<Token>,     // token produced by the last call to `scan_token`
+    reached_end: bool,          // whether the EOF token has been produced
+    report_errors: bool,        // whether lexical errors are reported or silently skipped
+    first_comment: Option<(usize, usize)>, // line and column of the first `//` comment scanned
+    interner: Interner,         // the identifiers and keywords seen so far, kept across `reset`
 }
 
 impl Lexer {
@@ -138,21 +208,74 @@ impl Lexer {
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
+            scanned: None,
+            reached_end: false,
+            report_errors: true,
+            first_comment: None,
+            interner: Interner::new(),
         }
     }
 
+    /// Turn reporting of lexical errors on or off, for scanning input that will be
+    /// scanned again later, like checking whether a line typed at the prompt is complete.
+    pub fn set_report_errors(&mut self, report_errors: bool) {
+        self.report_errors = report_errors;
+    }
+
+    /// Start over on a new source, as if freshly created, but keeping the allocations made
+    /// so far and the error reporting setting. This saves rebuilding a lexer for each of
+    /// many small inputs.
+    pub fn reset(&mut self, source: &str) {
+        self.source.clear();
+        self.source.push_str(source);
+        self.tokens.clear();
+        self.start = 0;
+        self.current = 0;
+        self.line = 1;
+        self.column = 1;
+        self.start_line = 1;
+        self.start_column = 1;
+        self.scanned = None;
+        self.reached_end = false;
+        self.first_comment = None;
+    }
+
+    /// Where the first `//` comment in the source is, once scanning has got past it.
+    /// Comments aren't part of the tree, so tools that rewrite the source can check this.
+    pub fn first_comment(&self) -> Option<(usize, usize)> {
+        self.first_comment
+    }
+
+    /// Scan the whole source into `tokens`, ending with an EOF token.
     pub fn scan_tokens(&mut self) {
-        loop {
-            if self.is_at_end() {
-                break;
-            }
+        while let Some(token) = self.next_token() {
+            self.tokens.push(token);
+        }
+    }
+
+    /// Scan only as far as needed to produce the next token. The last token is always
+    /// EOF, after which `None` is returned.
+    pub fn next_token(&mut self) -> Option<Token> {
+        while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column;
             self.scan_token();
+
+            if let Some(token) = self.scanned.take() {
+                return Some(token);
+            }
         }
 
-        self.tokens.push(
-            Token::new(TokenType::EOF, "".to_string(), Literal::Null, self.line)
-        )
+        if self.reached_end {
+            return None;
+        }
+
+        self.reached_end = true;
+        Some(Token::new(TokenType::EOF, "", Literal::Null, self.line, self.column, self.current, self.current))
     }
 
     pub fn scan_token(&mut self) {
@@ -163,13 +286,43 @@ impl Lexer {
             ')' => self.add_token(TokenType::RightParen, Literal::Null),
             '{' => self.add_token(TokenType::LeftBrace, Literal::Null),
             '}' => self.add_token(TokenType::RightBrace, Literal::Null),
+            '[' => self.add_token(TokenType::LeftBracket, Literal::Null),
+            ']' => self.add_token(TokenType::RightBracket, Literal::Null),
             ',' => self.add_token(TokenType::Comma, Literal::Null),
-            '.' => self.add_token(TokenType::Dot, Literal::Null),
+            '.' => {
+                if self.peek() == Some('.') && self.peek_next() == Some('.') {
+                    self.advance();
+                    self.advance();
+                    self.add_token(TokenType::DotDotDot, Literal::Null);
+                } else if self.peek() == Some('.') {
+                    self.advance();
+                    self.add_token(TokenType::DotDot, Literal::Null);
+                } else {
+                    self.add_token(TokenType::Dot, Literal::Null);
+                }
+            }
             ';' => self.add_token(TokenType::Semicolon, Literal::Null),
+            ':' => self.add_token(TokenType::Colon, Literal::Null),
             '-' => self.add_token(TokenType::Minus, Literal::Null),
             '+' => self.add_token(TokenType::Plus, Literal::Null),
+            // `//` starts a comment that runs to the end of the line
+            '/' if self.peek() == Some('/') => {
+                if self.first_comment.is_none() {
+                    self.first_comment = Some((self.line, self.start_column));
+                }
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.advance();
+                }
+            }
             '/' => self.add_token(TokenType::Slash, Literal::Null),
-            '*' => self.add_token(TokenType::Star, Literal::Null),
+            '*' => {
+                let token_type = self.next_char_equal('*', TokenType::StarStar, TokenType::Star);
+                self.add_token(token_type, Literal::Null);
+            }
+            '=' if self.peek() == Some('>') => {
+                self.advance();
+                self.add_token(TokenType::FatArrow, Literal::Null);
+            }
             '=' => {
                 let token_type = self.next_char_equal('=', TokenType::EqualEqual, TokenType::Equal);
                 self.add_token(token_type, Literal::Null);
@@ -186,18 +339,43 @@ impl Lexer {
                 let token_type = self.next_char_equal('=', TokenType::GreaterEqual, TokenType::Greater);
                 self.add_token(token_type, Literal::Null);
             }
+            '?' if self.peek() == Some('.') => {
+                self.advance();
+                self.add_token(TokenType::QuestionDot, Literal::Null);
+            }
+            // `&&` and `||` are spellings of `and` and `or`, single `&` and `|` are left free
+            '&' if self.peek() == Some('&') => {
+                self.advance();
+                self.add_token(TokenType::And, Literal::Null);
+            }
+            '|' if self.peek() == Some('|') => {
+                self.advance();
+                self.add_token(TokenType::Or, Literal::Null);
+            }
+            '|' if self.peek() == Some('>') => {
+                self.advance();
+                self.add_token(TokenType::Pipe, Literal::Null);
+            }
             '"' => {
                 self.string();
             }
+            // a `#!` line at the very start lets a script be run directly, like
+            // `#!/usr/bin/env roz`; it is skipped but still counts as line 1
+            '#' if self.start == 0 && self.peek() == Some('!') => {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.advance();
+                }
+            }
             '\n' => self.line += 1,
             ' ' | '\r' | '\t' => (),
             x => {
                 if x.is_alphabetic() || x == '_' {
                     self.identifier();
-                } else if x.is_digit(10) {
+                } else if x.is_ascii_digit() {
                     self.number();
                 } else {
-                    roz::lexical_error(self.line, &format!("Unexpected character: {}", c));
+                    let message = format!("Unexpected character: {}", c);
+                    self.error(self.start_column, &message);
                 }
             }
         }
@@ -205,60 +383,60 @@ impl Lexer {
 
     pub fn add_token(&mut self, token_type: TokenType, literal: Literal) {
         let text = &self.source[self.start..self.current];
-        self.tokens.push(
-            Token::new(token_type, text.to_string(), literal, self.line)
+        self.scanned = Some(
+            Token::new(token_type, text, literal, self.start_line, self.start_column, self.start, self.current)
         )
     }
 
     pub fn identifier(&mut self) {
-        loop {
-            if let Some(x) = self.peek() {
-                if x.is_alphanumeric() || x == '_' {
-                    self.advance();
-                } else {
-                    break;
-                }
+        while let Some(x) = self.peek() {
+            if x.is_alphanumeric() || x == '_' {
+                self.advance();
             } else {
                 break;
             }
         }
 
         let text = &self.source[self.start..self.current];
-
-        if let Some(token_type) = keywords().get(text) {
-            self.add_token(token_type.clone(), Literal::Null);
-        } else {
-            self.add_token(TokenType::Identifier, Literal::Null);
-        }
+        let token_type = keywords().get(text).cloned().unwrap_or(TokenType::Identifier);
+        let symbol = self.interner.intern(text);
+
+        self.scanned = Some(
+            Token::interned(
+                token_type,
+                self.interner.lookup(symbol),
+                self.start_line,
+                self.start_column,
+                self.start,
+                self.current,
+            )
+        )
     }
 
     pub fn string(&mut self) {
         loop {
-            if self.peek() == Some('\n') {
-                self.line += 1;
+            match self.peek() {
+                None => {
+                    self.error(self.column, "Unterminated string.");
+                    break;
+                }
+                Some('\n') => self.line += 1,
+                _ => (),
             }
-            
+
             if self.advance() == '"' {
                 let text = &self.source[self.start + 1..self.current - 1];
                 self.add_token(TokenType::String, Literal::String(text.to_string()));
                 break;
             }
-
-            if self.is_at_end() {
-                roz::lexical_error(self.line, "Unterminated string.");
-                break;
-            }
         }
     }
 
     pub fn number(&mut self) {
-        loop {
-            if let Some(x) = self.peek() {
-                if x.is_digit(10) {
-                    self.advance();
-                } else {
-                    break;
-                }
+        // underscores are taken in too, and `parse_number` checks they separate digits
+        while let Some(x) = self.peek() {
+            if x.is_ascii_digit() || x == '_' {
+                self.advance();
             } else {
                 break;
             }
@@ -266,32 +444,44 @@ impl Lexer {
 
         if let Some('.') = self.peek() {
             if let Some(x) = self.peek_next() {
-                if x.is_digit(10) {
+                if x.is_ascii_digit() {
                     self.advance();
                 }
             }
         }
 
-        loop {
-            if let Some(x) = self.peek() {
-                if x.is_digit(10) {
-                    self.advance();
-                } else {
-                    break;
-                }
+        while let Some(x) = self.peek() {
+            if x.is_ascii_digit() || x == '_' {
+                self.advance();
             } else {
                 break;
             }
         }
 
         let text = &self.source[self.start..self.current];
-        self.add_token(TokenType::Number, Literal::Number(text.parse::<f32>().unwrap()));
+        match literal::parse_number(text) {
+            Some(value) => self.add_token(TokenType::Number, Literal::Number(value)),
+            None => {
+                let message = format!("Invalid number: {}", text);
+                self.error(self.start_column, &message);
+            }
+        }
     }
 
+    /// Consume the next character. Callers check for the end first, but past it this
+    /// returns '\0', which matches nothing they look for, rather than panicking.
     pub fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
-        return c;
+        // `current` is a byte offset, so step over the whole UTF-8 encoding of the character
+        match self.source[self.current..].chars().next() {
+            Some(c) => {
+                self.current += c.len_utf8();
+                // every character counts one column, tabs included; they are expanded
+                // to tab stops only when a diagnostic is shown
+                self.column = if c == '\n' { 1 } else { self.column + 1 };
+                c
+            }
+            None => '\0',
+        }
     }
 
     pub fn next_char_equal(&mut self, c: char, equal_type: TokenType, unequal_type: TokenType) -> TokenType {
@@ -305,14 +495,109 @@ impl Lexer {
     }
 
     pub fn peek(&self) -> Option<char> {
-        self.source.chars().nth(self.current)
+        self.source[self.current..].chars().next()
     }
 
     pub fn peek_next(&self) -> Option<char> {
-        self.source.chars().nth(self.current + 1)
+        self.source[self.current..].chars().nth(1)
+    }
+
+    fn error(&self, column: usize, message: &str) {
+        if self.report_errors {
+            roz::lexical_error(self.line, column, message);
+        }
     }
 
     pub fn is_at_end(&self) -> bool {
         return self.current >= self.source.len()
     }
-}
\ No newline at end of file
+}
+
+impl Iterator for Lexer {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.next_token()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_identifiers_share_one_allocation() {
+        let mut lexer = Lexer::new("let total = total + count; print total;");
+        lexer.scan_tokens();
+
+        let names: Vec<&Token> = lexer.tokens.iter().filter(|token| token.token_type == TokenType::Identifier).collect();
+        let [first, second, count, third] = names[..] else {
+            panic!("expected four identifiers, got {:?}", names);
+        };
+        assert!(Rc::ptr_eq(&first.lexeme, &second.lexeme));
+        assert!(Rc::ptr_eq(&first.lexeme, &third.lexeme));
+        assert!(!Rc::ptr_eq(&first.lexeme, &count.lexeme));
+    }
+
+    #[test]
+    fn token_spans_are_the_lexemes_in_the_source() {
+        let source = "let größe = \"a b\";\nprint größe >= 1_000;";
+        let mut lexer = Lexer::new(source);
+        lexer.scan_tokens();
+
+        for token in &lexer.tokens {
+            assert_eq!(&source[token.start..token.end], &*token.lexeme);
+        }
+
+        let operator = lexer.tokens.iter().find(|token| token.token_type == TokenType::GreaterEqual).unwrap();
+        let start = source.find(">=").unwrap();
+        assert_eq!((operator.start, operator.end), (start, start + 2));
+    }
+
+    #[test]
+    fn iterating_gives_the_same_tokens_as_scan_tokens() {
+        let source = "fn f(a, b = 2) { return a ** b; }\nprint f(3) |> str;";
+        let mut lexer = Lexer::new(source);
+        lexer.scan_tokens();
+
+        let iterated: Vec<Token> = Lexer::new(source).collect();
+        assert_eq!(iterated, lexer.tokens);
+        assert_eq!(iterated.last().map(|token| &token.token_type), Some(&TokenType::EOF));
+    }
+
+    #[test]
+    fn columns_count_characters_from_the_start_of_each_line() {
+        let source = format!("{}x\n\tgröße = \"a\nb\" y", "a; ".repeat(10_000));
+        let mut lexer = Lexer::new(&source);
+        lexer.scan_tokens();
+
+        let columns: Vec<(usize, usize)> = lexer.tokens.iter().rev().take(5).map(|token| (token.line, token.column)).collect();
+        // EOF, `y`, the string starting on line 2 and running onto line 3, `=` and `größe`
+        assert_eq!(columns, [(3, 5), (3, 4), (2, 10), (2, 8), (2, 2)]);
+        assert_eq!(lexer.tokens[lexer.tokens.len() - 6].column, 30_001);
+    }
+
+    #[test]
+    fn a_reset_lexer_gives_the_same_tokens_as_a_fresh_one() {
+        let first = "let x = 1; // one\nprint x;";
+        let second = "fn f(a) {\n    return a * 2;\n}\nprint f(\"s\");";
+        let fresh = |source: &str| {
+            let mut lexer = Lexer::new(source);
+            lexer.scan_tokens();
+            lexer.tokens
+        };
+
+        let mut lexer = Lexer::new(first);
+        lexer.scan_tokens();
+        assert_eq!(lexer.tokens, fresh(first));
+
+        lexer.reset(second);
+        assert_eq!(lexer.first_comment(), None);
+        lexer.scan_tokens();
+        assert_eq!(lexer.tokens, fresh(second));
+
+        // scanning again changes nothing
+        lexer.scan_tokens();
+        assert_eq!(lexer.tokens, fresh(second));
+    }
+}
@@ -1,30 +1,77 @@
 use std::ops;
 use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::rc::Rc;
+use std::str::FromStr;
 
-use crate::function::Function;
+use crate::{callable::Callable, function::Function, native::{Memo, NativeFunction}, ordered_map::OrderedMap};
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub enum Literal {
     Number(f32),
     String(String),
     Bool(bool),
-    Function(Box<Function>),
+    Array(Rc<RefCell<Vec<Literal>>>),
+    Map(Rc<RefCell<OrderedMap<Literal>>>),   // iterated in the order keys were first added
+    Function(Rc<Function>),
+    Native(Rc<NativeFunction>),
+    Memo(Rc<Memo>),
     Null
 }
 
 impl Literal {
-    pub fn to_string(&self) -> String {
+    pub fn array(elements: Vec<Literal>) -> Self {
+        Literal::Array(Rc::new(RefCell::new(elements)))
+    }
+
+    pub fn map(entries: OrderedMap<Literal>) -> Self {
+        Literal::Map(Rc::new(RefCell::new(entries)))
+    }
+
+    /// The value as something that can be called, if it is a function.
+    pub fn as_callable(&self) -> Option<&dyn Callable> {
         match self {
-            Self::Number(x) => {
-                if x.to_string().ends_with(".0") || !x.to_string().contains('.') {
-                    return format!("{:.1}", x);
-                } else {
-                    return format!("{}", x);
-                }
+            Self::Function(func) => Some(func.as_ref()),
+            Self::Native(func) => Some(func.as_ref()),
+            Self::Memo(func) => Some(func.as_ref()),
+            _ => None,
+        }
+    }
+
+    /// A copy that shares no array or map with the original, however deeply nested.
+    pub fn deep_copy(&self) -> Literal {
+        match self {
+            Self::Array(elements) => {
+                Literal::array(elements.borrow().iter().map(|x| x.deep_copy()).collect())
             }
+            Self::Map(entries) => Literal::map(
+                entries.borrow().iter().map(|(key, value)| (key.clone(), value.deep_copy())).collect()
+            ),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        match self {
+            Self::Number(x) => format_number(*x),
             Self::String(x) => x.to_string(),
             Self::Bool(x) => x.to_string(),
+            Self::Array(elements) => {
+                let elements: Vec<String> = elements.borrow().iter().map(|x| x.to_string()).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Self::Map(entries) => {
+                let entries: Vec<String> = entries
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value.to_string()))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
             Self::Function(func) => format!("<fn {}>", func.name()),  
+            Self::Native(func) => format!("<native fn {}>", func.name()),
+            Self::Memo(func) => format!("<memo fn {}>", func.name()),
             Self::Null => "null".to_string()
         }
     }
@@ -34,35 +81,111 @@ impl Literal {
             Self::Number(_) => "number".to_string(),
             Self::String(_) => "string".to_string(),
             Self::Bool(_) => "bool".to_string(),
-            Self::Function(func) => format!("<fn {}>", func.name()),
+            Self::Array(_) => "array".to_string(),
+            Self::Map(_) => "map".to_string(),
+            Self::Function(_) | Self::Native(_) | Self::Memo(_) => "function".to_string(),
             Self::Null => "null".to_string(),
         }
     }
 
-    pub fn is_double(&self) -> bool {
-        match self {
-            Literal::Number(_) => true,
-            _ => false
+    /// Floored division, the quotient rounded towards negative infinity.
+    pub fn floor_div(self, rhs: Literal) -> Result<Literal, String> {
+        match (self, rhs) {
+            (Literal::Number(_), Literal::Number(0.0)) => Err("Division by zero.".to_string()),
+            (Literal::Number(x), Literal::Number(y)) => Ok(Literal::Number((x / y).floor())),
+            (lhs, rhs) => Err(format!("Cannot divide '{}' by '{}'", lhs.literal_type(), rhs.literal_type())),
         }
     }
 
+    pub fn is_double(&self) -> bool {
+        matches!(self, Literal::Number(_))
+    }
+
     pub fn is_bool(&self) -> bool {
-        match self {
-            Literal::Bool(_) => true,
-            _ => false,
-        }
+        matches!(self, Literal::Bool(_))
     }
 
     pub fn is_string(&self) -> bool {
-        match self {
-            Literal::String(_) => true,
-            _ => false,
-        }
+        matches!(self, Literal::String(_))
     }
 
     pub fn is_null(&self) -> bool {
-        match self {
-            Literal::Null => true,
+        matches!(self, Literal::Null)
+    }
+}
+
+/// Read a number written the way roz source writes one, digits with an optional fractional
+/// part, like `42` or `3.14`, allowing a leading `-`. Single underscores may separate
+/// digits, as in `1_000_000`. Forms Rust accepts but roz doesn't, like `1e5`, `.5` or
+/// `inf`, give `None`.
+pub fn parse_number(text: &str) -> Option<f32> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let (whole, fraction) = match digits.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (digits, None),
+    };
+
+    // every run of digits between underscores must be non-empty, so `1__0` and `1_` are out
+    let is_digits = |part: &str| {
+        part.split('_')
+            .all(|group| !group.is_empty() && group.bytes().all(|b| b.is_ascii_digit()))
+    };
+    if !is_digits(whole) || !fraction.is_none_or(is_digits) {
+        return None;
+    }
+
+    text.replace('_', "").parse().ok()
+}
+
+/// Read a value from text the way it would be written in roz: a number, `true`, `false`
+/// or `nil`. Any other text is taken as a string as it is, so parsing never fails.
+impl FromStr for Literal {
+    type Err = Infallible;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Ok(match text {
+            "true" => Literal::Bool(true),
+            "false" => Literal::Bool(false),
+            "nil" => Literal::Null,
+            _ => match parse_number(text) {
+                Some(x) => Literal::Number(x),
+                None => Literal::String(text.to_string()),
+            },
+        })
+    }
+}
+
+/// Format a number with as few digits as read back to the same value: whole numbers have
+/// no fractional part, and magnitudes too large to write out in full use an exponent.
+fn format_number(x: f32) -> String {
+    if x.is_nan() {
+        "NaN".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if x == 0.0 {
+        // negative zero too
+        "0".to_string()
+    } else if x.abs() >= 1e16 {
+        format!("{:e}", x)
+    } else {
+        format!("{}", x)
+    }
+}
+
+/// Arrays and maps are equal when their elements are, while functions are only equal
+/// to themselves: two definitions with the same body are still different functions.
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Literal::Number(x), Literal::Number(y)) => x == y,
+            (Literal::String(x), Literal::String(y)) => x == y,
+            (Literal::Bool(x), Literal::Bool(y)) => x == y,
+            (Literal::Array(x), Literal::Array(y)) => x == y,
+            (Literal::Map(x), Literal::Map(y)) => x == y,
+            (Literal::Function(x), Literal::Function(y)) => Rc::ptr_eq(x, y),
+            (Literal::Native(x), Literal::Native(y)) => Rc::ptr_eq(x, y),
+            (Literal::Memo(x), Literal::Memo(y)) => Rc::ptr_eq(x, y),
+            (Literal::Null, Literal::Null) => true,
             _ => false,
         }
     }
@@ -75,10 +198,16 @@ impl ops::Add<Literal> for Literal {
         match (self, rhs) {
             (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Number(lhs + rhs)),
             (Literal::String(lhs), Literal::String(rhs)) => Ok(Literal::String(lhs + &rhs)),
-            (Literal::Number(lhs), Literal::String(rhs)) => Ok(Literal::String(lhs.to_string() + &rhs)),
-            (Literal::String(lhs), Literal::Number(rhs)) => Ok(Literal::String(lhs + &rhs.to_string())),
+            (Literal::Number(lhs), Literal::String(rhs)) => Ok(Literal::String(format_number(lhs) + &rhs)),
+            (Literal::String(lhs), Literal::Number(rhs)) => Ok(Literal::String(lhs + &format_number(rhs))),
             (Literal::Bool(lhs), Literal::String(rhs)) => Ok(Literal::String(lhs.to_string() + &rhs)),
             (Literal::String(lhs), Literal::Bool(rhs)) => Ok(Literal::String(lhs + &rhs.to_string())),
+            (Literal::Array(lhs), Literal::Array(rhs)) => {
+                // a new array, so neither operand is changed by later updates to the result
+                let mut elements = lhs.borrow().clone();
+                elements.extend(rhs.borrow().iter().cloned());
+                Ok(Literal::array(elements))
+            }
             (lhs, rhs) => Err(format!("Cannot add '{}' and '{}'", lhs.literal_type(), rhs.literal_type()))
         }
     }
@@ -101,9 +230,13 @@ impl ops::Neg for Literal {
     fn neg(self) -> Self::Output {
         match self {
             Literal::Number(x) => Ok(Literal::Number(-x)),
-            Literal::Bool(x) => Ok(Literal::Bool(!x)),
+            Literal::Bool(_) => Err("Cannot negate a bool.".to_string()),
             Literal::String(_) => Err("Cannot negate a string.".to_string()),
-            Literal::Function(_) => Err("Cannot negate a function.".to_string()),
+            Literal::Array(_) => Err("Cannot negate an array.".to_string()),
+            Literal::Map(_) => Err("Cannot negate a map.".to_string()),
+            Literal::Function(_) | Literal::Native(_) | Literal::Memo(_) => {
+                Err("Cannot negate a function.".to_string())
+            }
             Literal::Null => Err("Cannot negate a nil.".to_string())
         }
     }
@@ -144,7 +277,24 @@ impl PartialOrd<Self> for Literal {
                     Some(Ordering::Equal)
                 }
             }
+            (Literal::String(x), Literal::String(y)) => Some(x.cmp(y)),
             (_, _) => None,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_parses_to_the_literal_it_would_be_in_roz() {
+        assert_eq!("42".parse::<Literal>(), Ok(Literal::Number(42.0)));
+        assert_eq!("-1.5".parse::<Literal>(), Ok(Literal::Number(-1.5)));
+        assert_eq!("true".parse::<Literal>(), Ok(Literal::Bool(true)));
+        assert_eq!("false".parse::<Literal>(), Ok(Literal::Bool(false)));
+        assert_eq!("nil".parse::<Literal>(), Ok(Literal::Null));
+        assert_eq!("hello world".parse::<Literal>(), Ok(Literal::String("hello world".to_string())));
+        assert_eq!("".parse::<Literal>(), Ok(Literal::String(String::new())));
+    }
+}
@@ -1,33 +1,114 @@
 use std::{
     env,
-    io::{self, Write},
-    process::ExitCode
+    io::{self, IsTerminal},
+    panic,
+    process::ExitCode,
+    thread,
 };
 
 pub mod callable;
 pub mod environment;
+pub mod formatter;
 pub mod function;
+pub mod interner;
 pub mod literal;
 pub mod lexer;
+pub mod native;
+pub mod ordered_map;
 pub mod parser;
 pub mod interpreter;
+pub mod regex;
+pub mod resolver;
 pub mod r#return;
 pub mod stmt;
 pub mod roz;
 
+fn usage(program: &str) -> ExitCode {
+    eprintln!("Usage: {} [options]", program);
+    eprintln!("Usage: {} [options] <filename> [arguments...]", program);
+    eprintln!("Usage: {} [options] - [arguments...]    (read the program from stdin)", program);
+    eprintln!("Usage: {} [options] fmt <filename>    (print the program in canonical form)", program);
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("    --auto-semicolon    treat a line break as the end of a complete statement");
+    eprintln!("    --strict            treat warnings as errors, and warn about functions that");
+    eprintln!("                        return a value on some paths but not others, and about");
+    eprintln!("                        '/' between integers where 'div' may have been meant");
+    eprintln!("    --sandbox           leave out builtins that access files or the environment");
+    eprintln!("    --time              report how long each phase of running took on stderr");
+    eprintln!("    --strict-truthiness treat 0 and empty strings, arrays and maps as false");
+    eprintln!("    --boolean-logical   make 'and' and 'or' give true or false, not an operand");
+    eprintln!("    --implicit-return   make a function without 'return' give the value of its");
+    eprintln!("                        last expression statement");
+    eprintln!("    --json-diagnostics  print errors and warnings to stderr as a JSON array");
+    eprintln!("    --json-diagnostics=<file>");
+    eprintln!("                        write the JSON array to <file> instead");
+    eprintln!("    --test              count failed asserts instead of stopping, and print a tally");
+    eprintln!("    --repl              start the prompt even when stdin isn't a terminal");
+    eprintln!("    --tab-width=N       expand tabs to every N columns when pointing at a problem");
+    eprintln!("                        in a source line (4 by default)");
+
+    ExitCode::from(64)
+}
+
 fn main() -> ExitCode {
+    // the main thread's stack is too small for the deepest expressions the parser accepts
+    let runner = thread::Builder::new()
+        .stack_size(roz::STACK_SIZE)
+        .spawn(start)
+        .expect("could not start the interpreter thread");
+
+    match runner.join() {
+        Ok(code) => code,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+fn start() -> ExitCode {
     let args: Vec<String> = env::args().collect();
-    
-    if args.len() == 1 {
-        roz::run_prompt();
-    } else if args.len() == 2 {
-            return roz::run_file(&args[1]);
-    } else {
-        if args.len() > 3 {
-            writeln!(io::stderr(), "Usage: {}", args[0]).unwrap();
-            writeln!(io::stderr(), "Usage: {} <filename>", args[0]).unwrap();
+    let mut config = roz::Config::default();
+    let mut repl = false;
+
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        match arg.as_str() {
+            "--auto-semicolon" => config.auto_semicolon = true,
+            "--strict" => config.strict = true,
+            "--sandbox" => config.sandbox = true,
+            "--time" => config.time = true,
+            "--strict-truthiness" => config.strict_truthiness = true,
+            "--boolean-logical" => config.boolean_logical = true,
+            "--implicit-return" => config.implicit_return = true,
+            "--json-diagnostics" => config.json_diagnostics = true,
+            flag if flag.starts_with("--json-diagnostics=") => match &flag["--json-diagnostics=".len()..] {
+                "" => return usage(&args[0]),
+                path => {
+                    config.json_diagnostics = true;
+                    config.json_diagnostics_file = Some(path.to_string());
+                }
+            },
+            "--test" => config.test = true,
+            "--repl" => repl = true,
+            flag if flag.starts_with("--tab-width=") => match flag["--tab-width=".len()..].parse() {
+                Ok(width) if width > 0 => roz::set_tab_width(width),
+                _ => return usage(&args[0]),
+            },
+            flag if flag.starts_with("--") => return usage(&args[0]),
+            _ => {
+                // everything from the filename on is for the script
+                config.args = args[i..].to_vec();
+                break;
+            }
         }
     }
-    
+
+    match config.args.first().map(String::as_str) {
+        // a program piped in without '-' is run just the same
+        None if !repl && !io::stdin().is_terminal() => return roz::run_stdin(&config),
+        None => roz::run_prompt(&config),
+        Some("-") => return roz::run_stdin(&config),
+        Some("fmt") if config.args.len() == 2 => return roz::format_file(&config.args[1], &config),
+        Some(filename) => return roz::run_file(filename, &config),
+    }
+
     ExitCode::SUCCESS
 }
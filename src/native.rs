@@ -0,0 +1,1034 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::HashMap,
+    env,
+    fmt,
+    fs,
+    io::{self, Write},
+    num::IntErrorKind,
+    process,
+    rc::Rc,
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    callable::Callable,
+    environment::Environment,
+    interpreter::{Interpreter, RuntimeError, RuntimeErrorKind, RuntimeException},
+    lexer::Token,
+    literal::{self, Literal},
+    ordered_map::OrderedMap,
+    regex::{Captures, Regex},
+    roz,
+};
+
+// the moment the first interpreter was set up, which `clock()` counts from
+static START: OnceLock<Instant> = OnceLock::new();
+
+// state of the generator behind `random()`, seeded from the time unless `seed(n)` is called
+static RANDOM: Mutex<Option<u64>> = Mutex::new(None);
+
+pub type NativeFn = fn(&mut Interpreter, &Token, Vec<Literal>) -> Result<Literal, RuntimeException>;
+
+/// A function implemented in Rust and exposed to roz programs as a global.
+#[derive(Clone)]
+pub struct NativeFunction {
+    name: &'static str,
+    arity: usize,
+    max_arity: usize,
+    function: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn new(name: &'static str, arity: usize, max_arity: usize, function: NativeFn) -> Self {
+        NativeFunction {
+            name,
+            arity,
+            max_arity,
+            function,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.to_string()
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Callable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn max_arity(&self) -> usize {
+        self.max_arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeException> {
+        (self.function)(interpreter, paren, arguments)
+    }
+}
+
+/// A function wrapped by `memo`, which remembers the result of each call and answers
+/// repeated calls with the same arguments from that record instead of calling again.
+#[derive(Debug)]
+pub struct Memo {
+    function: Literal,
+    cache: RefCell<HashMap<String, Literal>>,   // results keyed by the arguments they were computed for
+}
+
+impl Memo {
+    pub fn name(&self) -> String {
+        match &self.function {
+            Literal::Function(function) => function.name(),
+            Literal::Native(native) => native.name(),
+            Literal::Memo(memo) => memo.name(),
+            _ => String::new(),
+        }
+    }
+
+    fn callable(&self) -> &dyn Callable {
+        self.function.as_callable().expect("memo only wraps functions")
+    }
+}
+
+impl Callable for Memo {
+    fn arity(&self) -> usize {
+        self.callable().arity()
+    }
+
+    fn max_arity(&self) -> usize {
+        self.callable().max_arity()
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeException> {
+        // the type is part of the key so that 1 and "1.0" don't share an entry
+        let key: Vec<String> = arguments
+            .iter()
+            .map(|argument| format!("{}:{}", argument.literal_type(), argument.to_string()))
+            .collect();
+        let key = key.join(",");
+
+        if let Some(result) = self.cache.borrow().get(&key) {
+            return Ok(result.clone());
+        }
+
+        let result = interpreter.call_value(&self.function, paren, arguments)?;
+        self.cache.borrow_mut().insert(key, result.clone());
+        Ok(result)
+    }
+}
+
+/// Bind every native function in the given (global) environment.
+pub fn define_natives(environment: &mut Environment) {
+    let natives = [
+        NativeFunction::new("exit", 0, 1, exit),
+        NativeFunction::new("assert", 1, 2, assert),
+        NativeFunction::new("dbg", 1, 1, dbg),
+        NativeFunction::new("format", 1, usize::MAX, format),
+        NativeFunction::new("printf", 1, usize::MAX, printf),
+        NativeFunction::new("ord", 1, 1, ord),
+        NativeFunction::new("chr", 1, 1, chr),
+        NativeFunction::new("is_digit", 1, 1, is_digit),
+        NativeFunction::new("is_alpha", 1, 1, is_alpha),
+        NativeFunction::new("is_space", 1, 1, is_space),
+        NativeFunction::new("abs", 1, 1, abs),
+        NativeFunction::new("sign", 1, 1, sign),
+        NativeFunction::new("clamp", 3, 3, clamp),
+        NativeFunction::new("pow", 2, 2, pow),
+        NativeFunction::new("trunc", 1, 1, trunc),
+        NativeFunction::new("fract", 1, 1, fract),
+        NativeFunction::new("is_nan", 1, 1, is_nan),
+        NativeFunction::new("is_infinite", 1, 1, is_infinite),
+        NativeFunction::new("parse_number", 1, 1, parse_number),
+        NativeFunction::new("sort", 1, 2, sort),
+        NativeFunction::new("copy", 1, 1, copy),
+        NativeFunction::new("keys", 1, 1, keys),
+        NativeFunction::new("values", 1, 1, values),
+        NativeFunction::new("has", 2, 2, has),
+        NativeFunction::new("remove", 2, 2, remove),
+        NativeFunction::new("args", 0, 0, args),
+        NativeFunction::new("clock", 0, 0, clock),
+        NativeFunction::new("sleep", 1, 1, sleep),
+        NativeFunction::new("type", 1, 1, type_of),
+        NativeFunction::new("is_nil", 1, 1, is_nil),
+        NativeFunction::new("memo", 1, 1, memo),
+        NativeFunction::new("regex_match", 2, 2, regex_match),
+        NativeFunction::new("regex_replace", 3, 3, regex_replace),
+        NativeFunction::new("to_radix", 2, 2, to_radix),
+        NativeFunction::new("from_radix", 2, 2, from_radix),
+        NativeFunction::new("contains", 2, 2, contains),
+        NativeFunction::new("starts_with", 2, 2, starts_with),
+        NativeFunction::new("ends_with", 2, 2, ends_with),
+        NativeFunction::new("index_of", 2, 2, index_of),
+        NativeFunction::new("eq_ignore_case", 2, 2, eq_ignore_case),
+        NativeFunction::new("replace", 3, 3, replace),
+        NativeFunction::new("replace_first", 3, 3, replace_first),
+        NativeFunction::new("join", 2, 2, join),
+        NativeFunction::new("range", 1, 1, range),
+        NativeFunction::new("enumerate", 1, 1, enumerate),
+        NativeFunction::new("zip", 2, 2, zip),
+        NativeFunction::new("random", 0, 0, random),
+        NativeFunction::new("random_int", 2, 2, random_int),
+        NativeFunction::new("seed", 1, 1, seed),
+    ];
+
+    for native in natives {
+        environment.define(native.name(), Literal::Native(Rc::new(native)));
+    }
+
+    environment.define_constant("PI".to_string(), Literal::Number(std::f32::consts::PI));
+    environment.define_constant("E".to_string(), Literal::Number(std::f32::consts::E));
+
+    START.get_or_init(Instant::now);
+}
+
+/// Bind the native functions that access the system outside of the interpreter, which
+/// are left out of sandboxed interpreters.
+pub fn define_system_natives(environment: &mut Environment) {
+    let natives = [
+        NativeFunction::new("read_file", 1, 1, read_file),
+        NativeFunction::new("write_file", 2, 2, write_file),
+        NativeFunction::new("env", 1, 2, env),
+    ];
+
+    for native in natives {
+        environment.define(native.name(), Literal::Native(Rc::new(native)));
+    }
+}
+
+fn error(paren: &Token, message: &str) -> RuntimeException {
+    RuntimeException::Error(RuntimeError::new(RuntimeErrorKind::InvalidArgument, paren.clone(), message.to_string()))
+}
+
+/// `exit(code)` flushes stdout and ends the process with the given code, 0 if it is omitted or nil.
+/// The process ends immediately, so nothing in the script runs after it, error handling included.
+fn exit(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let code = match arguments.first() {
+        None | Some(Literal::Null) => 0,
+        Some(Literal::Number(code)) if code.fract() == 0.0 && (0.0..=255.0).contains(code) => *code as i32,
+        Some(_) => return Err(error(paren, "Exit code must be an integer between 0 and 255.")),
+    };
+
+    let _ = io::stdout().flush();
+    process::exit(code);
+}
+
+/// `assert(condition, message)` does nothing when `condition` is true, and is otherwise a
+/// runtime error saying `message`. Under `--test`, a failure is reported and counted instead,
+/// and the program carries on.
+fn assert(interpreter: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let passed = interpreter.is_true(&arguments[0]);
+    let message = match arguments.get(1) {
+        None | Some(Literal::Null) => "Assertion failed.".to_string(),
+        Some(message) => format!("Assertion failed: {}", message.to_string()),
+    };
+
+    if !interpreter.test_mode() {
+        if passed {
+            return Ok(Literal::Null);
+        }
+        let error = RuntimeError::new(RuntimeErrorKind::AssertionFailed, paren.clone(), message);
+        return Err(RuntimeException::Error(error));
+    }
+
+    interpreter.record_assertion(passed);
+    if !passed {
+        roz::assertion_failure(paren, &message);
+    }
+    Ok(Literal::Null)
+}
+
+/// `dbg(x)` prints `x` to stderr along with the line it was called from, and gives `x`
+/// back, so it can be wrapped around any expression without changing what it does.
+fn dbg(_: &mut Interpreter, paren: &Token, mut arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let value = arguments.remove(0);
+    eprintln!("[line {}] {}", paren.line, value.to_string());
+    Ok(value)
+}
+
+/// `format(fmt, ...args)` fills each `{}` in `fmt` with the next argument. `{:N}` prints a
+/// number with `N` digits after the decimal point, and `{{`/`}}` produce literal braces.
+fn format(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let mut arguments = arguments.into_iter();
+
+    let template = match arguments.next() {
+        Some(Literal::String(template)) => template,
+        _ => return Err(error(paren, "The first argument to format must be a string.")),
+    };
+
+    let mut output = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '{' => {
+                let mut spec = String::new();
+                let mut closed = false;
+
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    spec.push(c);
+                }
+
+                if !closed {
+                    return Err(error(paren, "Unclosed '{' in format string."));
+                }
+
+                let argument = match arguments.next() {
+                    Some(argument) => argument,
+                    None => return Err(error(paren, "Too few arguments for format string.")),
+                };
+
+                if spec.is_empty() {
+                    output.push_str(&argument.to_string());
+                    continue;
+                }
+
+                let precision = match spec.strip_prefix(':').map(|digits| digits.parse::<usize>()) {
+                    Some(Ok(precision)) => precision,
+                    _ => {
+                        let message = format!("Malformed placeholder '{{{}}}' in format string.", spec);
+                        return Err(error(paren, &message));
+                    }
+                };
+
+                match argument {
+                    Literal::Number(x) => output.push_str(&format!("{:.*}", precision, x)),
+                    _ => return Err(error(paren, "A precision can only be applied to a number.")),
+                }
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '}' => return Err(error(paren, "Unmatched '}' in format string.")),
+            c => output.push(c),
+        }
+    }
+
+    if arguments.next().is_some() {
+        return Err(error(paren, "Too many arguments for format string."));
+    }
+
+    Ok(Literal::String(output))
+}
+
+/// `printf(fmt, ...args)` prints the result of `format(fmt, ...args)` without a trailing newline.
+fn printf(interpreter: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let output = format(interpreter, paren, arguments)?;
+
+    print!("{}", output.to_string());
+    let _ = io::stdout().flush();
+
+    Ok(Literal::Null)
+}
+
+fn char_argument(function: &str, paren: &Token, arguments: &[Literal]) -> Result<char, RuntimeException> {
+    let string = match &arguments[0] {
+        Literal::String(string) => string,
+        _ => return Err(error(paren, &format!("{} expects a string.", function))),
+    };
+
+    let mut chars = string.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(error(paren, &format!("{} expects a string of exactly one character.", function))),
+    }
+}
+
+/// `ord(s)` returns the Unicode code point of the single character in `s`.
+fn ord(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let c = char_argument("ord", paren, &arguments)?;
+    Ok(Literal::Number(c as u32 as f32))
+}
+
+/// `chr(n)` returns the one-character string for the Unicode code point `n`.
+fn chr(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let code = match &arguments[0] {
+        Literal::Number(code) if code.fract() == 0.0 && *code >= 0.0 => *code as u32,
+        _ => return Err(error(paren, "chr expects a non-negative integer.")),
+    };
+
+    match char::from_u32(code) {
+        Some(c) => Ok(Literal::String(c.to_string())),
+        None => Err(error(paren, &format!("{} is not a valid character code.", code))),
+    }
+}
+
+/// `is_digit(c)` tells whether the one-character string `c` is one of 0 to 9.
+fn is_digit(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let c = char_argument("is_digit", paren, &arguments)?;
+    Ok(Literal::Bool(c.is_ascii_digit()))
+}
+
+/// `is_alpha(c)` tells whether the one-character string `c` is a letter, in any alphabet.
+fn is_alpha(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let c = char_argument("is_alpha", paren, &arguments)?;
+    Ok(Literal::Bool(c.is_alphabetic()))
+}
+
+/// `is_space(c)` tells whether the one-character string `c` is whitespace.
+fn is_space(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let c = char_argument("is_space", paren, &arguments)?;
+    Ok(Literal::Bool(c.is_whitespace()))
+}
+
+fn number_arguments(function: &str, paren: &Token, arguments: &[Literal]) -> Result<Vec<f32>, RuntimeException> {
+    arguments
+        .iter()
+        .map(|argument| match argument {
+            Literal::Number(x) => Ok(*x),
+            _ if arguments.len() == 1 => Err(error(paren, &format!("{} expects a number.", function))),
+            _ => Err(error(paren, &format!("{} expects numbers.", function))),
+        })
+        .collect()
+}
+
+/// `abs(x)` returns `x` without its sign.
+fn abs(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let x = number_arguments("abs", paren, &arguments)?[0];
+    Ok(Literal::Number(x.abs()))
+}
+
+/// `sign(x)` returns -1 for a negative `x`, 1 for a positive one and 0 for zero.
+fn sign(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let x = number_arguments("sign", paren, &arguments)?[0];
+    // `signum` would give 1 for zero
+    Ok(Literal::Number(if x == 0.0 { 0.0 } else { x.signum() }))
+}
+
+/// `clamp(x, lo, hi)` returns `x` moved into the range from `lo` to `hi`.
+fn clamp(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let numbers = number_arguments("clamp", paren, &arguments)?;
+    let (x, lo, hi) = (numbers[0], numbers[1], numbers[2]);
+
+    // `f32::clamp` would panic on these
+    if lo.is_nan() || hi.is_nan() || lo > hi {
+        return Err(error(paren, "clamp expects lo to be no greater than hi."));
+    }
+    Ok(Literal::Number(x.clamp(lo, hi)))
+}
+
+/// `pow(base, exp)` is `base ** exp` written as a call.
+fn pow(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let numbers = number_arguments("pow", paren, &arguments)?;
+    Ok(Literal::Number(numbers[0].powf(numbers[1])))
+}
+
+/// `trunc(x)` returns the integer part of `x`, dropping any fraction towards zero.
+fn trunc(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let x = number_arguments("trunc", paren, &arguments)?[0];
+    Ok(Literal::Number(x.trunc()))
+}
+
+/// `fract(x)` returns the fractional part of `x`, which has the same sign as `x`.
+fn fract(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let x = number_arguments("fract", paren, &arguments)?[0];
+    Ok(Literal::Number(x.fract()))
+}
+
+/// `is_nan(x)` tells whether `x` is NaN, as given by `0 / 0`.
+fn is_nan(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let x = number_arguments("is_nan", paren, &arguments)?[0];
+    Ok(Literal::Bool(x.is_nan()))
+}
+
+/// `is_infinite(x)` tells whether `x` is infinite, as given by `1 / 0`.
+fn is_infinite(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let x = number_arguments("is_infinite", paren, &arguments)?[0];
+    Ok(Literal::Bool(x.is_infinite()))
+}
+
+/// `parse_number(s)` reads `s` as a number by the same rules as number literals in source.
+fn parse_number(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let text = match &arguments[0] {
+        Literal::String(text) => text,
+        _ => return Err(error(paren, "parse_number expects a string.")),
+    };
+
+    match literal::parse_number(text) {
+        Some(x) => Ok(Literal::Number(x)),
+        None => Err(error(paren, &format!("'{}' is not a valid number.", text))),
+    }
+}
+
+/// `sort(array, cmp)` returns a sorted copy of `array`. Without `cmp`, the elements must
+/// be all numbers or all strings; `cmp(a, b)` otherwise returns a negative number when
+/// `a` goes first, a positive one when `b` does, and zero when either order will do.
+fn sort(interpreter: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let elements = match &arguments[0] {
+        Literal::Array(elements) => elements.borrow().clone(),
+        _ => return Err(error(paren, "sort expects an array.")),
+    };
+
+    let sorted = match arguments.get(1) {
+        None | Some(Literal::Null) => merge_sort(elements, &mut |a, b| {
+            a.partial_cmp(b).ok_or_else(|| {
+                let message = format!("Can't compare {} with {}.", a.literal_type(), b.literal_type());
+                error(paren, &message)
+            })
+        })?,
+        Some(comparator) => merge_sort(elements, &mut |a, b| {
+            match interpreter.call_value(comparator, paren, vec![a.clone(), b.clone()])? {
+                Literal::Number(x) if x < 0.0 => Ok(Ordering::Less),
+                Literal::Number(x) if x > 0.0 => Ok(Ordering::Greater),
+                Literal::Number(_) => Ok(Ordering::Equal),
+                _ => Err(error(paren, "The comparator passed to sort must return a number.")),
+            }
+        })?,
+    };
+
+    Ok(Literal::array(sorted))
+}
+
+/// A stable merge sort that stops at the first failed comparison. Unlike `slice::sort_by`,
+/// it stays well-behaved when a user comparator isn't a consistent total order.
+fn merge_sort(
+    mut elements: Vec<Literal>,
+    compare: &mut dyn FnMut(&Literal, &Literal) -> Result<Ordering, RuntimeException>,
+) -> Result<Vec<Literal>, RuntimeException> {
+    if elements.len() <= 1 {
+        return Ok(elements);
+    }
+
+    let right = elements.split_off(elements.len() / 2);
+    let left = merge_sort(elements, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if compare(a, b)? == Ordering::Greater {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// `copy(x)` returns a deep copy of `x`, so changing the copy never changes the original.
+fn copy(_: &mut Interpreter, _: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    Ok(arguments[0].deep_copy())
+}
+
+/// The entries of the map passed as the first argument of `function`.
+fn map_argument(
+    function: &str,
+    paren: &Token,
+    arguments: &[Literal],
+) -> Result<Rc<RefCell<OrderedMap<Literal>>>, RuntimeException> {
+    match &arguments[0] {
+        Literal::Map(entries) => Ok(entries.clone()),
+        _ => Err(error(paren, &format!("{} expects a map.", function))),
+    }
+}
+
+/// `keys(m)` returns an array of the keys of `m`, in the order they were first added.
+fn keys(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let entries = map_argument("keys", paren, &arguments)?;
+    let keys = entries.borrow().keys().map(|key| Literal::String(key.clone())).collect();
+    Ok(Literal::array(keys))
+}
+
+/// `values(m)` returns an array of the values of `m`, in the same order as `keys(m)`.
+fn values(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let entries = map_argument("values", paren, &arguments)?;
+    let values = entries.borrow().values().cloned().collect();
+    Ok(Literal::array(values))
+}
+
+/// `has(m, k)` tells whether `m` has the key `k`.
+fn has(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let entries = map_argument("has", paren, &arguments)?;
+    let has = match &arguments[1] {
+        Literal::String(key) => entries.borrow().contains_key(key),
+        _ => false,
+    };
+    Ok(Literal::Bool(has))
+}
+
+/// `remove(m, k)` deletes the key `k` from `m`, returning its value, or nil if there was none.
+fn remove(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let entries = map_argument("remove", paren, &arguments)?;
+    let removed = match &arguments[1] {
+        Literal::String(key) => entries.borrow_mut().remove(key),
+        _ => None,
+    };
+    Ok(removed.unwrap_or(Literal::Null))
+}
+
+/// `args()` returns the path of the running script followed by the arguments given after it.
+fn args(interpreter: &mut Interpreter, _: &Token, _: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let args = interpreter.args().iter().map(|arg| Literal::String(arg.clone())).collect();
+    Ok(Literal::array(args))
+}
+
+/// `clock()` returns the number of seconds since the interpreter started, for timing code.
+fn clock(_: &mut Interpreter, _: &Token, _: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let start = START.get_or_init(Instant::now);
+    Ok(Literal::Number(start.elapsed().as_secs_f32()))
+}
+
+/// `sleep(ms)` pauses for `ms` milliseconds. Nothing else runs in the meantime, since the
+/// whole interpreter is blocked.
+fn sleep(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    match &arguments[0] {
+        Literal::Number(ms) if *ms >= 0.0 && ms.is_finite() => {
+            thread::sleep(Duration::from_secs_f32(ms / 1000.0));
+            Ok(Literal::Null)
+        }
+        _ => Err(error(paren, "sleep expects a non-negative number of milliseconds.")),
+    }
+}
+
+/// `type(x)` returns the name of the type of `x`, like "number" or "function".
+fn type_of(_: &mut Interpreter, _: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    Ok(Literal::String(arguments[0].literal_type()))
+}
+
+/// `is_nil(x)` tells whether `x` is `nil`, the same as `x == nil`.
+fn is_nil(_: &mut Interpreter, _: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    Ok(Literal::Bool(arguments[0].is_null()))
+}
+
+/// `memo(f)` returns a function that calls `f` once for each distinct list of arguments
+/// and remembers the result. Arguments are told apart by their printed form.
+fn memo(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let function = arguments.into_iter().next().unwrap();
+
+    if function.as_callable().is_none() {
+        return Err(error(paren, "memo expects a function."));
+    }
+
+    Ok(Literal::Memo(Rc::new(Memo {
+        function,
+        cache: RefCell::new(HashMap::new()),
+    })))
+}
+
+/// Compile the pattern given to the native `function`, making an invalid one a runtime error.
+fn compile_pattern(function: &str, paren: &Token, pattern: &Literal) -> Result<Regex, RuntimeException> {
+    match pattern {
+        Literal::String(pattern) => Regex::new(pattern).map_err(|message| error(paren, &message)),
+        _ => Err(error(paren, &format!("{} expects a pattern string.", function))),
+    }
+}
+
+/// `regex_match(pattern, text)` searches `text` for the first match of `pattern`. It returns
+/// nil if there is none, and otherwise an array of the whole match followed by the text of
+/// each group, nil for a group that took no part in the match. See `regex.rs` for the syntax.
+fn regex_match(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let regex = compile_pattern("regex_match", paren, &arguments[0])?;
+    let text: Vec<char> = match &arguments[1] {
+        Literal::String(text) => text.chars().collect(),
+        _ => return Err(error(paren, "regex_match expects a text string.")),
+    };
+
+    let captures = match regex.captures(&text, 0) {
+        Some(captures) => captures,
+        None => return Ok(Literal::Null),
+    };
+
+    let groups = captures
+        .into_iter()
+        .map(|span| match span {
+            Some((start, end)) => Literal::String(text[start..end].iter().collect()),
+            None => Literal::Null,
+        })
+        .collect();
+
+    Ok(Literal::array(groups))
+}
+
+/// `regex_replace(pattern, text, replacement)` returns `text` with every match of `pattern`
+/// replaced. In `replacement`, `$0` stands for the whole match, `$1` and on for the groups,
+/// and `$$` for a literal dollar sign.
+fn regex_replace(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let regex = compile_pattern("regex_replace", paren, &arguments[0])?;
+    let (text, replacement) = match (&arguments[1], &arguments[2]) {
+        (Literal::String(text), Literal::String(replacement)) => (text.chars().collect::<Vec<_>>(), replacement),
+        _ => return Err(error(paren, "regex_replace expects a text string and a replacement string.")),
+    };
+
+    let mut output = String::new();
+    let mut position = 0;
+
+    while let Some(captures) = regex.captures(&text, position) {
+        let (start, end) = captures[0].unwrap();
+        output.extend(&text[position..start]);
+        output.push_str(&expand_replacement(paren, replacement, &captures, &text)?);
+
+        // step over an empty match, or the same one would be found again
+        if end == start {
+            output.extend(text.get(end));
+            position = end + 1;
+        } else {
+            position = end;
+        }
+
+        if position > text.len() {
+            break;
+        }
+    }
+
+    output.extend(text.get(position..).unwrap_or_default());
+    Ok(Literal::String(output))
+}
+
+fn expand_replacement(
+    paren: &Token,
+    replacement: &str,
+    captures: &Captures,
+    text: &[char],
+) -> Result<String, RuntimeException> {
+    let mut output = String::new();
+    let mut chars = replacement.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            output.push(c);
+            continue;
+        }
+
+        if chars.next_if_eq(&'$').is_some() {
+            output.push('$');
+            continue;
+        }
+
+        let mut digits = String::new();
+        while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+            digits.push(digit);
+        }
+
+        let group = match digits.parse::<usize>() {
+            Ok(group) if group < captures.len() => group,
+            _ => return Err(error(paren, "'$' in a replacement must be followed by a group number or '$'.")),
+        };
+
+        if let Some((start, end)) = captures[group] {
+            output.extend(&text[start..end]);
+        }
+    }
+
+    Ok(output)
+}
+
+/// The base passed as the second argument of `function`, which must be an integer from 2 to 36.
+fn radix_argument(function: &str, paren: &Token, arguments: &[Literal]) -> Result<u32, RuntimeException> {
+    match &arguments[1] {
+        Literal::Number(base) if base.fract() == 0.0 && (2.0..=36.0).contains(base) => Ok(*base as u32),
+        _ => Err(error(paren, &format!("{} expects a base between 2 and 36.", function))),
+    }
+}
+
+/// `to_radix(n, base)` writes the integer part of `n` in `base`, using the letters a to z
+/// for digits above 9, e.g. `to_radix(255, 16)` is "ff".
+fn to_radix(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let base = radix_argument("to_radix", paren, &arguments)?;
+    let n = match &arguments[0] {
+        // `i64::MAX as f32` rounds up to 2^63, so the range is exactly the numbers an `i64` holds
+        Literal::Number(n) if (i64::MIN as f32..i64::MAX as f32).contains(&n.trunc()) => n.trunc() as i64,
+        Literal::Number(n) if n.is_finite() => {
+            return Err(error(paren, &format!("{} is out of range for to_radix.", arguments[0].to_string())))
+        }
+        _ => return Err(error(paren, "to_radix expects a finite number.")),
+    };
+
+    let mut digits = Vec::new();
+    let mut rest = n.unsigned_abs();
+
+    loop {
+        digits.push(char::from_digit((rest % base as u64) as u32, base).unwrap());
+        rest /= base as u64;
+        if rest == 0 {
+            break;
+        }
+    }
+
+    if n < 0 {
+        digits.push('-');
+    }
+
+    Ok(Literal::String(digits.iter().rev().collect()))
+}
+
+/// `from_radix(s, base)` reads the integer written in `base` in `s`, the reverse of `to_radix`.
+/// Letters may be either case, and a digit that isn't valid in `base` is an error.
+fn from_radix(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let base = radix_argument("from_radix", paren, &arguments)?;
+    let text = match &arguments[0] {
+        Literal::String(text) => text,
+        _ => return Err(error(paren, "from_radix expects a string.")),
+    };
+
+    match i64::from_str_radix(text, base) {
+        Ok(n) => Ok(Literal::Number(n as f32)),
+        Err(err) if matches!(err.kind(), IntErrorKind::PosOverflow | IntErrorKind::NegOverflow) => {
+            Err(error(paren, &format!("'{}' is out of range for from_radix.", text)))
+        }
+        Err(_) => Err(error(paren, &format!("'{}' is not an integer in base {}.", text, base))),
+    }
+}
+
+/// The two strings passed to `function`, for the natives that search one string for another.
+fn string_pair<'a>(function: &str, paren: &Token, arguments: &'a [Literal]) -> Result<(&'a str, &'a str), RuntimeException> {
+    match (&arguments[0], &arguments[1]) {
+        (Literal::String(s), Literal::String(sub)) => Ok((s, sub)),
+        _ => Err(error(paren, &format!("{} expects two strings.", function))),
+    }
+}
+
+/// `contains(s, sub)` tells whether `sub` appears anywhere in `s`.
+fn contains(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (s, sub) = string_pair("contains", paren, &arguments)?;
+    Ok(Literal::Bool(s.contains(sub)))
+}
+
+/// `starts_with(s, prefix)` tells whether `s` begins with `prefix`.
+fn starts_with(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (s, prefix) = string_pair("starts_with", paren, &arguments)?;
+    Ok(Literal::Bool(s.starts_with(prefix)))
+}
+
+/// `ends_with(s, suffix)` tells whether `s` finishes with `suffix`.
+fn ends_with(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (s, suffix) = string_pair("ends_with", paren, &arguments)?;
+    Ok(Literal::Bool(s.ends_with(suffix)))
+}
+
+/// `index_of(s, sub)` returns the position of the first `sub` in `s`, counted in characters
+/// like string indexing, or -1 if `sub` doesn't appear.
+fn index_of(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (s, sub) = string_pair("index_of", paren, &arguments)?;
+    let index = match s.find(sub) {
+        Some(byte) => s[..byte].chars().count() as f32,
+        None => -1.0,
+    };
+    Ok(Literal::Number(index))
+}
+
+/// `eq_ignore_case(a, b)` tells whether two strings are equal once both are lowercased,
+/// which covers letters beyond ASCII, e.g. "ÉTÉ" and "été".
+fn eq_ignore_case(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (a, b) = string_pair("eq_ignore_case", paren, &arguments)?;
+    Ok(Literal::Bool(a.to_lowercase() == b.to_lowercase()))
+}
+
+/// The string, pattern and replacement passed to `function`. An empty pattern is rejected,
+/// since it would match between every two characters.
+fn replace_arguments<'a>(
+    function: &str,
+    paren: &Token,
+    arguments: &'a [Literal],
+) -> Result<(&'a str, &'a str, &'a str), RuntimeException> {
+    match (&arguments[0], &arguments[1], &arguments[2]) {
+        (Literal::String(_), Literal::String(from), Literal::String(_)) if from.is_empty() => {
+            Err(error(paren, &format!("{} can't replace an empty string.", function)))
+        }
+        (Literal::String(s), Literal::String(from), Literal::String(to)) => Ok((s, from, to)),
+        _ => Err(error(paren, &format!("{} expects three strings.", function))),
+    }
+}
+
+/// `replace(s, from, to)` returns `s` with every `from` replaced by `to`.
+fn replace(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (s, from, to) = replace_arguments("replace", paren, &arguments)?;
+    Ok(Literal::String(s.replace(from, to)))
+}
+
+/// `replace_first(s, from, to)` returns `s` with only the first `from` replaced by `to`.
+fn replace_first(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (s, from, to) = replace_arguments("replace_first", paren, &arguments)?;
+    Ok(Literal::String(s.replacen(from, to, 1)))
+}
+
+/// `join(array, sep)` returns the printed form of each element of `array`, with `sep`
+/// between every two of them.
+fn join(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (elements, separator) = match (&arguments[0], &arguments[1]) {
+        (Literal::Array(elements), Literal::String(separator)) => (elements, separator),
+        _ => return Err(error(paren, "join expects an array and a separator string.")),
+    };
+
+    let parts: Vec<String> = elements.borrow().iter().map(|element| element.to_string()).collect();
+    Ok(Literal::String(parts.join(separator)))
+}
+
+// the longest array `range` makes: past 2^24, numbers can't tell consecutive integers apart
+const MAX_RANGE: f32 = 16_777_216.0;
+
+/// `range(n)` returns the array `[0, 1, ..., n - 1]`.
+fn range(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let n = match &arguments[0] {
+        Literal::Number(n) if *n > MAX_RANGE => {
+            return Err(error(paren, &format!("range can't count past {}.", Literal::Number(MAX_RANGE).to_string())));
+        }
+        Literal::Number(n) if n.fract() == 0.0 && *n >= 0.0 => *n as usize,
+        _ => return Err(error(paren, "range expects a non-negative integer.")),
+    };
+
+    Ok(Literal::array((0..n).map(|i| Literal::Number(i as f32)).collect()))
+}
+
+/// `enumerate(array)` returns an array of `[index, element]` pairs.
+fn enumerate(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let elements = match &arguments[0] {
+        Literal::Array(elements) => elements,
+        _ => return Err(error(paren, "enumerate expects an array.")),
+    };
+
+    let pairs = elements
+        .borrow()
+        .iter()
+        .enumerate()
+        .map(|(i, element)| Literal::array(vec![Literal::Number(i as f32), element.clone()]))
+        .collect();
+
+    Ok(Literal::array(pairs))
+}
+
+/// `zip(a, b)` returns an array of `[a[i], b[i]]` pairs, as long as the shorter of the two.
+fn zip(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (a, b) = match (&arguments[0], &arguments[1]) {
+        (Literal::Array(a), Literal::Array(b)) => (a, b),
+        _ => return Err(error(paren, "zip expects two arrays.")),
+    };
+
+    let pairs = a
+        .borrow()
+        .iter()
+        .zip(b.borrow().iter())
+        .map(|(x, y)| Literal::array(vec![x.clone(), y.clone()]))
+        .collect();
+
+    Ok(Literal::array(pairs))
+}
+
+/// The next number from a splitmix64 generator, which is small and good enough for scripts
+/// though not for anything that needs to be unpredictable.
+fn next_random() -> u64 {
+    let mut state = RANDOM.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let seed = state.get_or_insert_with(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64)
+    });
+
+    *seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// `random()` returns a number from 0 up to but not including 1.
+fn random(_: &mut Interpreter, _: &Token, _: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    // 24 random bits fill the mantissa of an f32 exactly, so the result never rounds up to 1
+    Ok(Literal::Number((next_random() >> 40) as f32 / (1u32 << 24) as f32))
+}
+
+/// `random_int(lo, hi)` returns an integer from `lo` up to but not including `hi`.
+fn random_int(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (lo, hi) = match (&arguments[0], &arguments[1]) {
+        (Literal::Number(lo), Literal::Number(hi)) if lo.fract() == 0.0 && hi.fract() == 0.0 => {
+            (*lo as i64, *hi as i64)
+        }
+        _ => return Err(error(paren, "random_int expects two integers.")),
+    };
+
+    if lo >= hi {
+        return Err(error(paren, "random_int expects the lower bound to be less than the upper one."));
+    }
+
+    let offset = next_random() % (hi - lo) as u64;
+    Ok(Literal::Number((lo + offset as i64) as f32))
+}
+
+/// `seed(n)` restarts the generator behind `random()` and `random_int()` from `n`, so the
+/// same seed always gives the same sequence.
+fn seed(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let seed = match &arguments[0] {
+        Literal::Number(n) if n.fract() == 0.0 => *n as i64 as u64,
+        _ => return Err(error(paren, "seed expects an integer.")),
+    };
+
+    *RANDOM.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(seed);
+    Ok(Literal::Null)
+}
+
+/// `read_file(path)` returns the contents of the file at `path` as a string.
+fn read_file(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let path = match &arguments[0] {
+        Literal::String(path) => path,
+        _ => return Err(error(paren, "read_file expects a path string.")),
+    };
+
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Literal::String(contents)),
+        Err(err) => Err(error(paren, &format!("Failed to read file '{}': {}.", path, err))),
+    }
+}
+
+/// `write_file(path, contents)` writes the string `contents` to the file at `path`,
+/// replacing anything that was there.
+fn write_file(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let (path, contents) = match (&arguments[0], &arguments[1]) {
+        (Literal::String(path), Literal::String(contents)) => (path, contents),
+        _ => return Err(error(paren, "write_file expects a path string and a contents string.")),
+    };
+
+    match fs::write(path, contents) {
+        Ok(()) => Ok(Literal::Null),
+        Err(err) => Err(error(paren, &format!("Failed to write file '{}': {}.", path, err))),
+    }
+}
+
+/// `env(name, default)` returns the value of the environment variable `name`, or `default`
+/// (nil when omitted) if it isn't set.
+fn env(_: &mut Interpreter, paren: &Token, arguments: Vec<Literal>) -> Result<Literal, RuntimeException> {
+    let name = match &arguments[0] {
+        Literal::String(name) => name,
+        _ => return Err(error(paren, "env expects a variable name string.")),
+    };
+
+    match env::var(name) {
+        Ok(value) => Ok(Literal::String(value)),
+        Err(_) => Ok(arguments.get(1).cloned().unwrap_or(Literal::Null)),
+    }
+}
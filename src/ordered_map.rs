@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+/// A map from strings to values that remembers the order its keys were first inserted in,
+/// so iterating it, and so printing it, gives the same order on every run.
+#[derive(Debug, Clone)]
+pub struct OrderedMap<V> {
+    entries: Vec<(String, V)>,
+    indices: HashMap<String, usize>,    // position of each key in `entries`
+}
+
+impl<V> Default for OrderedMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> OrderedMap<V> {
+    pub fn new() -> Self {
+        OrderedMap {
+            entries: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.indices.get(key).map(|&index| &self.entries[index].1)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        match self.indices.get(key) {
+            Some(&index) => Some(&mut self.entries[index].1),
+            None => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    /// Set the value of a key, returning the value it replaced. A key that was already
+    /// present keeps its place in the order.
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        match self.indices.get(&key) {
+            Some(&index) => Some(std::mem::replace(&mut self.entries[index].1, value)),
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Remove a key, returning its value. The keys after it move up, keeping their order.
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let index = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+
+        for (key, _) in &self.entries[index..] {
+            if let Some(position) = self.indices.get_mut(key) {
+                *position -= 1;
+            }
+        }
+
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+/// Two maps are equal when they have the same entries, whatever order they were added in.
+impl<V: PartialEq> PartialEq for OrderedMap<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<V> FromIterator<(String, V)> for OrderedMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
+        let mut map = OrderedMap::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl<V> IntoIterator for OrderedMap<V> {
+    type Item = (String, V);
+    type IntoIter = std::vec::IntoIter<(String, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
@@ -1,8 +1,7 @@
 use crate::{
-    lexer::{Token, TokenType},
+    lexer::{Lexer, Token, TokenType},
     literal::Literal,
-    roz,
-    stmt::{Expr, Stmt},
+    stmt::{Expr, LambdaDecl, Stmt},
 };
 
 #[derive(Debug)]
@@ -11,15 +10,134 @@ pub struct ParseError {
     pub message: String,
 }
 
+// parameters with optional defaults, rest parameter, body
+type FunctionParts = (Vec<(Token, Option<Expr>)>, Option<Token>, Stmt);
+
+// deep enough for any program written by hand, and shallow enough that parsing,
+// resolving and running the result all fit on the stack of a debug build
+const DEFAULT_MAX_DEPTH: usize = 128;
+
+// how tall an expression may grow through operators and postfix calls, indexing and property
+// access, which the parser handles in loops rather than by recursion. What runs a program
+// still recurses once per level, so this bounds its stack use too.
+const DEFAULT_MAX_HEIGHT: usize = 10_000;
+
+const DEFAULT_MAX_ARGUMENTS: usize = 255;
+
 #[derive(Clone)]
 pub struct Parser {
     tokens: Vec<Token>,
+    lexer: Option<Lexer>,   // source of further tokens, if they are pulled lazily
     current: usize,
+    auto_semicolon: bool,
+    depth: usize,       // expressions and statements currently being parsed inside one another
+    max_depth: usize,
+    height: usize,      // how tall the expression last parsed at this depth is, see `grow`
+    max_height: usize,
+    max_arguments: usize,   // most parameters a function may declare, or arguments a call may pass
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            lexer: None,
+            current: 0,
+            auto_semicolon: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            height: 0,
+            max_height: DEFAULT_MAX_HEIGHT,
+            max_arguments: DEFAULT_MAX_ARGUMENTS,
+        }
+    }
+
+    /// Create a parser that pulls tokens from the lexer as it needs them, only
+    /// keeping the previous token and a small lookahead in memory.
+    pub fn from_lexer(lexer: Lexer) -> Self {
+        let mut parser = Self {
+            tokens: Vec::new(),
+            lexer: Some(lexer),
+            current: 0,
+            auto_semicolon: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            height: 0,
+            max_height: DEFAULT_MAX_HEIGHT,
+            max_arguments: DEFAULT_MAX_ARGUMENTS,
+        };
+        parser.fill();
+        parser
+    }
+
+    /// When enabled, a line break ends a statement that is otherwise complete,
+    /// so the trailing ';' can be left out.
+    pub fn set_auto_semicolon(&mut self, auto_semicolon: bool) {
+        self.auto_semicolon = auto_semicolon;
+    }
+
+    /// How deeply expressions and statements may nest, as in `((((1))))`, before parsing
+    /// stops with an error.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// How tall an expression may grow through chains like `1 + 2 + 3` or `f()()[0].x`,
+    /// counting one level for each operator or postfix, before parsing stops with an error.
+    pub fn set_max_height(&mut self, max_height: usize) {
+        self.max_height = max_height;
+    }
+
+    /// How many parameters a function may declare, and how many arguments a call may
+    /// pass, counting keyword arguments, before parsing stops with an error.
+    pub fn set_max_arguments(&mut self, max_arguments: usize) {
+        self.max_arguments = max_arguments;
+    }
+
+    /// Run a parsing function one level of nesting deeper, failing with `message`
+    /// past the maximum depth rather than overflowing the stack.
+    fn nested<T>(
+        &mut self,
+        message: &str,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError {
+                token: self.peek().clone(),
+                message: message.to_string(),
+            });
+        }
+
+        // what is parsed at the new depth goes one level below what came before it here
+        let outer = std::mem::replace(&mut self.height, 0);
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+
+        self.height = outer.max(self.height + 1);
+        result
+    }
+
+    /// Start on the right operand of a binary operator, or anything else that sits beside
+    /// the expression parsed so far, returning the height of what came before.
+    fn beside(&mut self) -> usize {
+        std::mem::replace(&mut self.height, 0)
+    }
+
+    /// Count the level added by an operator or postfix over an expression whose left side was
+    /// `left` tall, failing past the maximum height rather than building a tree too tall to run.
+    fn grow(&mut self, left: usize, operator: &Token) -> Result<(), ParseError> {
+        self.height = left.max(self.height) + 1;
+
+        if self.height > self.max_height {
+            return Err(ParseError {
+                token: operator.clone(),
+                message: "Expression nesting too deep.".to_string(),
+            });
+        }
+
+        Ok(())
     }
 
     pub fn parse(&mut self) -> Result<Vec<Stmt>, ParseError> {
@@ -32,12 +150,32 @@ impl Parser {
         Ok(statements)
     }
 
+    /// Parse a source that holds a single expression and nothing after it.
+    pub fn parse_expression(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.expression()?;
+
+        if !self.is_at_end() {
+            return Err(ParseError {
+                token: self.peek().clone(),
+                message: "Expected end of expression.".to_string(),
+            });
+        }
+
+        Ok(expr)
+    }
+
     pub fn declaration(&mut self) -> Result<Stmt, ParseError> {
         if self.match_token_type(&[TokenType::Let]) {
             return self.var_declaration();
         }
 
-        if self.match_token_type(&[TokenType::Fn]) {
+        if self.match_token_type(&[TokenType::Const]) {
+            return self.const_declaration();
+        }
+
+        // without a name, `fn` starts an anonymous function expression instead
+        if self.check(&TokenType::Fn) && self.check_next(&TokenType::Identifier) {
+            self.advance();
             return self.fn_declaration("function");
         }
 
@@ -53,19 +191,62 @@ impl Parser {
             TokenType::LeftParen,
             &format!("Expected '(' after {} name", kind),
         )?;
-        let mut parameters = Vec::new();
+        let (parameters, rest, body) = self.function_rest(kind)?;
+
+        // an arrow body is an expression, so the declaration ends like any statement
+        if body.arrow_value().is_some() {
+            self.consume_terminator(&format!("Expected ';' after {} body", kind))?;
+        }
+
+        Ok(Stmt::Function(name, parameters, rest, Box::new(body)))
+    }
+
+    /// Parse the parameters and body of a function, starting after the `(`.
+    pub fn function_rest(
+        &mut self,
+        kind: &str,
+    ) -> Result<FunctionParts, ParseError> {
+        let mut parameters: Vec<(Token, Option<Expr>)> = Vec::new();
+        let mut rest = None;
         if !self.check(&TokenType::RightParen) {
             loop {
-                if parameters.len() >= 255 {
+                if parameters.len() >= self.max_arguments {
                     return Err(ParseError {
                         token: self.peek().clone(),
-                        message: "Can't have more than 255 parameters.".to_string(),
+                        message: format!("Can't have more than {} parameters.", self.max_arguments),
                     });
                 }
-                parameters.push(
-                    self.consume(TokenType::Identifier, "Expected parameter name")?
-                        .clone(),
-                );
+                if self.match_token_type(&[TokenType::DotDotDot]) {
+                    rest = Some(
+                        self.consume(TokenType::Identifier, "Expected rest parameter name")?
+                            .clone(),
+                    );
+
+                    if !self.check(&TokenType::RightParen) {
+                        return Err(ParseError {
+                            token: self.peek().clone(),
+                            message: "A rest parameter must be the last parameter.".to_string(),
+                        });
+                    }
+                    break;
+                }
+
+                let parameter = self
+                    .consume(TokenType::Identifier, "Expected parameter name")?
+                    .clone();
+
+                let mut default = None;
+                if self.match_token_type(&[TokenType::Equal]) {
+                    default = Some(self.expression()?);
+                } else if parameters.iter().any(|(_, default)| default.is_some()) {
+                    return Err(ParseError {
+                        token: parameter,
+                        message: "Parameters without a default value must come before those with one."
+                            .to_string(),
+                    });
+                }
+
+                parameters.push((parameter, default));
 
                 if !self.match_token_type(&[TokenType::Comma]) {
                     break;
@@ -74,31 +255,121 @@ impl Parser {
         }
         self.consume(TokenType::RightParen, "Expected ')' after parameters")?;
 
+        // `=> value` is short for `{ return value; }`
+        if self.match_token_type(&[TokenType::FatArrow]) {
+            let arrow = self.previous().clone();
+            let value = self.expression()?;
+            return Ok((parameters, rest, Stmt::Block(vec![Stmt::Return(arrow, value)])));
+        }
+
         self.consume(
             TokenType::LeftBrace,
             &format!("Expected '{{' before {} body", kind),
         )?;
-        let body = self.block()?;
+        let body = self.nested("Statement nesting too deep.", Self::block)?;
 
-        Ok(Stmt::Function(name, parameters, Box::new(body)))
+        Ok((parameters, rest, body))
     }
 
     pub fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token_type(&[TokenType::LeftBracket]) {
+            return self.destructuring_declaration();
+        }
+
+        let mut declarations = Vec::new();
+
+        loop {
+            let name = self
+                .consume(TokenType::Identifier, "Expected variable name")?
+                .clone();
+
+            let mut initializer = Expr::Literal(Literal::Null);
+            if self.match_token_type(&[TokenType::Equal]) {
+                initializer = self.expression()?;
+            }
+
+            // `let x = value else { ... }` only binds a value that isn't nil
+            if declarations.is_empty() && self.match_token_type(&[TokenType::Else]) {
+                self.consume(TokenType::LeftBrace, "Expected '{' after 'else' in a 'let' declaration.")?;
+                let else_branch = self.nested("Statement nesting too deep.", Self::block)?;
+                self.match_token_type(&[TokenType::Semicolon]);
+                return Ok(Stmt::LetElse(name, initializer, Box::new(else_branch)));
+            }
+
+            declarations.push((name, initializer));
+
+            if !self.match_token_type(&[TokenType::Comma]) {
+                break;
+            }
+        }
+
+        self.consume_terminator("Expected ';'")?;
+
+        if declarations.len() == 1 {
+            let (name, initializer) = declarations.remove(0);
+            return Ok(Stmt::Var(name, initializer));
+        }
+
+        return Ok(Stmt::VarMulti(declarations));
+    }
+
+    /// Parses `let [a, b, ...rest] = expr;` after the opening bracket.
+    pub fn destructuring_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let bracket = self.previous().clone();
+
+        let mut names = Vec::new();
+        let mut rest = None;
+        if !self.check(&TokenType::RightBracket) {
+            loop {
+                if self.match_token_type(&[TokenType::DotDotDot]) {
+                    rest = Some(
+                        self.consume(TokenType::Identifier, "Expected rest variable name")?
+                            .clone(),
+                    );
+                    break;
+                }
+
+                names.push(
+                    self.consume(TokenType::Identifier, "Expected variable name")?
+                        .clone(),
+                );
+
+                if !self.match_token_type(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expected ']' after destructuring pattern")?;
+
+        self.consume(TokenType::Equal, "Expected '=' after destructuring pattern")?;
+        let initializer = self.expression()?;
+
+        self.consume_terminator("Expected ';'")?;
+
+        return Ok(Stmt::Destructure(bracket, names, rest, initializer));
+    }
+
+    pub fn const_declaration(&mut self) -> Result<Stmt, ParseError> {
         let name = self
-            .consume(TokenType::Identifier, "Expected variable name")?
+            .consume(TokenType::Identifier, "Expected constant name")?
             .clone();
 
-        let mut initializer = Expr::Literal(Literal::Null);
-        if self.match_token_type(&[TokenType::Equal]) {
-            initializer = self.expression()?;
-        }
+        self.consume(
+            TokenType::Equal,
+            "Expected '=' after constant name, constants must be initialized",
+        )?;
+        let initializer = self.expression()?;
 
-        self.consume(TokenType::Semicolon, "Expected ';'")?;
+        self.consume_terminator("Expected ';'")?;
 
-        return Ok(Stmt::Var(name, initializer));
+        return Ok(Stmt::Const(name, initializer));
     }
 
     pub fn statement(&mut self) -> Result<Stmt, ParseError> {
+        self.nested("Statement nesting too deep.", Self::any_statement)
+    }
+
+    fn any_statement(&mut self) -> Result<Stmt, ParseError> {
         if self.match_token_type(&[TokenType::Print]) {
             return self.print_statement();
         }
@@ -111,76 +382,242 @@ impl Parser {
             return self.if_statement();
         }
 
+        if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Colon) {
+            return self.labeled_statement();
+        }
+
         if self.match_token_type(&[TokenType::While]) {
-            return self.while_statement();
+            return self.while_statement(None);
+        }
+
+        if self.match_token_type(&[TokenType::Do]) {
+            return self.do_while_statement(None);
+        }
+
+        if self.match_token_type(&[TokenType::Repeat]) {
+            return self.repeat_statement(None);
+        }
+
+        if self.match_token_type(&[TokenType::Switch]) {
+            return self.switch_statement();
         }
 
         if self.match_token_type(&[TokenType::For]) {
-            return self.for_statement();
+            return self.for_statement(None);
         }
 
         if self.match_token_type(&[TokenType::Return]) {
             return self.return_statement();
         }
 
+        if self.match_token_type(&[TokenType::Break]) {
+            let keyword = self.previous().clone();
+            let label = self.loop_label();
+            self.consume_terminator("Expected ';' after 'break'.")?;
+            return Ok(Stmt::Break(keyword, label));
+        }
+
+        if self.match_token_type(&[TokenType::Continue]) {
+            let keyword = self.previous().clone();
+            let label = self.loop_label();
+            self.consume_terminator("Expected ';' after 'continue'.")?;
+            return Ok(Stmt::Continue(keyword, label));
+        }
+
         return self.expression_statement();
     }
 
+    /// A loop with a label in front, as in `outer: while (...) ...`.
+    pub fn labeled_statement(&mut self) -> Result<Stmt, ParseError> {
+        let label = self.advance().clone();
+        self.advance();
+
+        if self.match_token_type(&[TokenType::While]) {
+            return self.while_statement(Some(label));
+        }
+
+        if self.match_token_type(&[TokenType::Do]) {
+            return self.do_while_statement(Some(label));
+        }
+
+        if self.match_token_type(&[TokenType::Repeat]) {
+            return self.repeat_statement(Some(label));
+        }
+
+        if self.match_token_type(&[TokenType::For]) {
+            return self.for_statement(Some(label));
+        }
+
+        Err(ParseError {
+            token: label,
+            message: "Only loops can be labeled.".to_string(),
+        })
+    }
+
+    /// The optional label after `break` or `continue`.
+    pub fn loop_label(&mut self) -> Option<Token> {
+        if !self.at_implicit_terminator() && self.match_token_type(&[TokenType::Identifier]) {
+            return Some(self.previous().clone());
+        }
+
+        None
+    }
+
     pub fn return_statement(&mut self) -> Result<Stmt, ParseError> {
         let keyword = self.previous().clone();
 
         let mut value = Expr::None;
-        if !self.check(&TokenType::Semicolon) {
+        if !self.check(&TokenType::Semicolon) && !self.at_implicit_terminator() {
             value = self.expression()?;
         }
 
-        self.consume(TokenType::Semicolon, "Expected ';' after return value.")?;
+        self.consume_terminator("Expected ';' after return value.")?;
 
         Ok(Stmt::Return(keyword, value))
     }
 
     pub fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         let expr = self.expression()?;
 
-        self.consume(TokenType::Semicolon, "';' expected.")?;
+        self.consume_terminator("';' expected.")?;
 
-        return Ok(Stmt::Print(expr));
+        return Ok(Stmt::Print(keyword, expr));
     }
 
     pub fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let expr = self.expression()?;
 
-        self.consume(TokenType::Semicolon, "';' expected.")?;
+        self.consume_terminator("';' expected.")?;
 
         return Ok(Stmt::Expression(expr));
     }
 
+    /// Parse an `if` along with any `else if` arms after it into a single statement, so a
+    /// long chain doesn't nest one level deeper for each arm.
     pub fn if_statement(&mut self) -> Result<Stmt, ParseError> {
-        self.consume(TokenType::LeftParen, "Expected '(' before expression.")?;
-        let expr = self.expression()?;
-        self.consume(TokenType::RightParen, "Expected ')' after expression.")?;
+        let mut arms = Vec::new();
+
+        loop {
+            self.consume(TokenType::LeftParen, "Expected '(' before expression.")?;
+            let expr = self.expression()?;
+            self.consume(TokenType::RightParen, "Expected ')' after expression.")?;
 
-        let then_stmt = self.statement()?;
+            arms.push((expr, self.statement()?));
+
+            if !(self.check(&TokenType::Else) && self.check_next(&TokenType::If)) {
+                break;
+            }
+            self.advance();
+            self.advance();
+        }
 
         let mut else_stmt = Stmt::None;
         if self.match_token_type(&[TokenType::Else]) {
             else_stmt = self.statement()?;
         }
 
-        Ok(Stmt::If(expr, Box::new(then_stmt), Box::new(else_stmt)))
+        Ok(Stmt::If(arms, Box::new(else_stmt)))
     }
 
-    pub fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+    pub fn while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expected '(' before expression.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expected ')' after expression.")?;
 
         let body = self.statement()?;
+        let else_branch = self.loop_else()?;
 
-        Ok(Stmt::While(condition, Box::new(body)))
+        Ok(Stmt::While(condition, Box::new(body), Expr::None, Box::new(else_branch), label))
     }
 
-    pub fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+    /// The optional `else` after a loop body, run when the loop ends without a `break`.
+    pub fn loop_else(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token_type(&[TokenType::Else]) {
+            return self.statement();
+        }
+
+        Ok(Stmt::None)
+    }
+
+    /// Parses `repeat (count) body` after the `repeat`.
+    pub fn repeat_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        self.consume(TokenType::LeftParen, "Expected '(' after 'repeat'.")?;
+        let count = self.expression()?;
+        self.consume(TokenType::RightParen, "Expected ')' after repeat count.")?;
+
+        let body = self.statement()?;
+
+        Ok(Stmt::Repeat(keyword, count, Box::new(body), label))
+    }
+
+    pub fn do_while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let body = self.statement()?;
+
+        self.consume(TokenType::While, "Expected 'while' after do body.")?;
+        self.consume(TokenType::LeftParen, "Expected '(' before expression.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expected ')' after expression.")?;
+        self.consume_terminator("Expected ';' after do-while condition.")?;
+
+        Ok(Stmt::DoWhile(Box::new(body), condition, label))
+    }
+
+    pub fn switch_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::LeftParen, "Expected '(' before expression.")?;
+        let subject = self.expression()?;
+        self.consume(TokenType::RightParen, "Expected ')' after expression.")?;
+
+        self.consume(TokenType::LeftBrace, "Expected '{' before switch body.")?;
+
+        let mut cases = Vec::new();
+        let mut default = None;
+
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if self.match_token_type(&[TokenType::Case]) {
+                let value = self.expression()?;
+                self.consume(TokenType::Colon, "Expected ':' after case value.")?;
+                cases.push((value, self.switch_arm()?));
+            } else if self.match_token_type(&[TokenType::Default]) {
+                if default.is_some() {
+                    return Err(ParseError {
+                        token: self.previous().clone(),
+                        message: "A switch can only have one default case.".to_string(),
+                    });
+                }
+                self.consume(TokenType::Colon, "Expected ':' after 'default'.")?;
+                default = Some(self.switch_arm()?);
+            } else {
+                return Err(ParseError {
+                    token: self.peek().clone(),
+                    message: "Expected 'case' or 'default'.".to_string(),
+                });
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expected '}' after switch body.")?;
+
+        Ok(Stmt::Switch(subject, cases, default))
+    }
+
+    /// The statements of a switch arm run until the next `case`, `default` or the closing brace.
+    fn switch_arm(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut statements = Vec::new();
+
+        while !self.check(&TokenType::Case)
+            && !self.check(&TokenType::Default)
+            && !self.check(&TokenType::RightBrace)
+            && !self.is_at_end()
+        {
+            statements.push(self.declaration()?);
+        }
+
+        Ok(statements)
+    }
+
+    pub fn for_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expected '(' before expressions.")?;
 
         let initializer;
@@ -205,16 +642,14 @@ impl Parser {
         self.consume(TokenType::RightParen, "Expected ')' after for clauses.")?;
 
         let mut body = self.statement()?;
-
-        if increment != Expr::None {
-            body = Stmt::Block(Vec::from([body, Stmt::Expression(increment)]));
-        }
+        let else_branch = self.loop_else()?;
 
         if condition == Expr::None {
             condition = Expr::Literal(Literal::Bool(true));
         }
 
-        body = Stmt::While(condition, Box::new(body));
+        // the increment is kept apart from the body so that `continue` doesn't skip it
+        body = Stmt::While(condition, Box::new(body), increment, Box::new(else_branch), label);
 
         if initializer != Stmt::None {
             body = Stmt::Block(Vec::from([initializer, body]));
@@ -236,20 +671,28 @@ impl Parser {
     }
 
     pub fn expression(&mut self) -> Result<Expr, ParseError> {
-        self.assignment()
+        self.nested("Expression nesting too deep.", Self::assignment)
     }
 
     pub fn assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.or()?;
+        let expr = self.pipe()?;
 
         if self.match_token_type(&[TokenType::Equal]) {
             let equals = self.previous().clone();
-            let value = self.assignment()?;
+            let value = self.expression()?;
 
             match expr {
                 Expr::Variable(name) => {
                     return Ok(Expr::Assign(name, Box::new(value)));
                 }
+                Expr::Index(object, bracket, index) => {
+                    return Ok(Expr::SetIndex(object, bracket, index, Box::new(value)));
+                }
+                // `m.key = value` is the same as `m["key"] = value`
+                Expr::Get(object, name, false) => {
+                    let key = Expr::Literal(Literal::String(name.lexeme.to_string()));
+                    return Ok(Expr::SetIndex(object, name, Box::new(key), Box::new(value)));
+                }
                 _ => {
                     return Err(ParseError {
                         token: equals.clone(),
@@ -262,12 +705,39 @@ impl Parser {
         Ok(expr)
     }
 
+    /// `x |> f` is a call `f(x)`. When the right side is already a call, `x` goes in
+    /// front of its arguments, so `x |> add(3)` is `add(x, 3)`. Pipes chain to the left:
+    /// `x |> f |> g` is `g(f(x))`.
+    pub fn pipe(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.or()?;
+
+        while self.match_token_type(&[TokenType::Pipe]) {
+            let operator = self.previous().clone();
+            let left = self.beside();
+            let right = self.or()?;
+            self.grow(left, &operator)?;
+
+            expr = match right {
+                Expr::Call(callee, paren, arguments, keywords) => {
+                    let mut arguments = arguments;
+                    arguments.insert(0, expr);
+                    Expr::Call(callee, paren, arguments, keywords)
+                }
+                callee => Expr::Call(Box::new(callee), operator, vec![expr], Vec::new()),
+            };
+        }
+
+        return Ok(expr);
+    }
+
     pub fn or(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.and()?;
 
         while self.match_token_type(&[TokenType::Or]) {
             let operator = self.previous().clone();
+            let left = self.beside();
             let right = self.and()?;
+            self.grow(left, &operator)?;
 
             expr = Expr::Logical(Box::new(expr), operator, Box::new(right));
         }
@@ -280,7 +750,9 @@ impl Parser {
 
         while self.match_token_type(&[TokenType::And]) {
             let operator = self.previous().clone();
-            let right = self.and()?;
+            let left = self.beside();
+            let right = self.equality()?;
+            self.grow(left, &operator)?;
 
             expr = Expr::Logical(Box::new(expr), operator, Box::new(right));
         }
@@ -293,7 +765,9 @@ impl Parser {
 
         while self.match_token_type(&[TokenType::BangEqual, TokenType::EqualEqual]) {
             let operator = self.previous().clone();
+            let left = self.beside();
             let right = self.comparison()?;
+            self.grow(left, &operator)?;
 
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
         }
@@ -311,7 +785,9 @@ impl Parser {
             TokenType::LessEqual,
         ]) {
             let operator = self.previous().clone();
+            let left = self.beside();
             let right = self.term()?;
+            self.grow(left, &operator)?;
 
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
         }
@@ -324,7 +800,9 @@ impl Parser {
 
         while self.match_token_type(&[TokenType::Plus, TokenType::Minus]) {
             let operator = self.previous().clone();
+            let left = self.beside();
             let right = self.factor()?;
+            self.grow(left, &operator)?;
 
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
         }
@@ -335,9 +813,11 @@ impl Parser {
     pub fn factor(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.unary()?;
 
-        while self.match_token_type(&[TokenType::Star, TokenType::Slash]) {
+        while self.match_token_type(&[TokenType::Star, TokenType::Slash, TokenType::Div]) {
             let operator = self.previous().clone();
+            let left = self.beside();
             let right = self.unary()?;
+            self.grow(left, &operator)?;
 
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
         }
@@ -345,42 +825,114 @@ impl Parser {
         return Ok(expr);
     }
 
+    /// Unary `+` is accepted for symmetry with `-`: it leaves a number unchanged
+    /// and is a runtime error on anything else.
     pub fn unary(&mut self) -> Result<Expr, ParseError> {
-        if self.match_token_type(&[TokenType::Bang, TokenType::Minus]) {
+        if self.match_token_type(&[TokenType::Bang, TokenType::Minus, TokenType::Plus]) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
+            let right = self.nested("Expression nesting too deep.", Self::unary)?;
 
             return Ok(Expr::Unary(operator, Box::new(right)));
         }
 
-        return self.call();
+        return self.power();
+    }
+
+    /// `**` binds tighter than a unary operator on its left and groups to the right, so
+    /// `-2 ** 2` is `-(2 ** 2)` and `2 ** 3 ** 2` is `2 ** (3 ** 2)`. The exponent may
+    /// itself start with a unary operator, as in `2 ** -1`.
+    pub fn power(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.call()?;
+
+        if self.match_token_type(&[TokenType::StarStar]) {
+            let operator = self.previous().clone();
+            let right = self.nested("Expression nesting too deep.", Self::unary)?;
+
+            return Ok(Expr::Binary(Box::new(expr), operator, Box::new(right)));
+        }
+
+        return Ok(expr);
     }
 
     pub fn call(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.primary()?;
 
         loop {
+            // the arguments and index are parsed as expressions of their own, a level down
+            let left = self.height;
+
             if self.match_token_type(&[TokenType::LeftParen]) {
                 expr = self.finish_call(expr)?;
+            } else if self.match_token_type(&[TokenType::LeftBracket]) {
+                expr = self.finish_index(expr)?;
+            } else if self.match_token_type(&[TokenType::Dot, TokenType::QuestionDot]) {
+                let safe = self.previous().token_type == TokenType::QuestionDot;
+                let name = self
+                    .consume(TokenType::Identifier, "Expected property name after '.'.")?
+                    .clone();
+                expr = Expr::Get(Box::new(expr), name, safe);
             } else {
                 break;
             }
+
+            let postfix = self.previous().clone();
+            self.grow(left, &postfix)?;
         }
 
         Ok(expr)
     }
 
+    /// Parse what follows the `[` of `object[index]` or `object[start..end]`,
+    /// where either bound of a slice may be left out.
+    pub fn finish_index(&mut self, object: Expr) -> Result<Expr, ParseError> {
+        let bracket = self.previous().clone();
+
+        let start = if self.check(&TokenType::DotDot) {
+            Expr::None
+        } else {
+            self.expression()?
+        };
+
+        if self.match_token_type(&[TokenType::DotDot]) {
+            let end = if self.check(&TokenType::RightBracket) {
+                Expr::None
+            } else {
+                self.expression()?
+            };
+
+            self.consume(TokenType::RightBracket, "Expected ']' after slice.")?;
+            return Ok(Expr::Slice(Box::new(object), bracket, Box::new(start), Box::new(end)));
+        }
+
+        self.consume(TokenType::RightBracket, "Expected ']' after index.")?;
+        Ok(Expr::Index(Box::new(object), bracket, Box::new(start)))
+    }
+
     pub fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
         let mut arguments = Vec::new();
+        let mut keywords = Vec::new();
 
         if !self.check(&TokenType::RightParen) {
             loop {
-                if arguments.len() >= 255 {
-                    // we are returning a error here because the parser is still in a valid state.
-                    roz::error(self.peek(), "Can't have more than 255 arguments.");
+                if arguments.len() + keywords.len() >= self.max_arguments {
+                    return Err(ParseError {
+                        token: self.peek().clone(),
+                        message: format!("Can't have more than {} arguments.", self.max_arguments),
+                    });
                 }
 
-                arguments.push(self.expression()?);
+                if self.check(&TokenType::Identifier) && self.check_next(&TokenType::Colon) {
+                    let name = self.advance().clone();
+                    self.advance();
+                    keywords.push((name, self.expression()?));
+                } else if !keywords.is_empty() {
+                    return Err(ParseError {
+                        token: self.peek().clone(),
+                        message: "Positional arguments can't follow keyword arguments.".to_string(),
+                    });
+                } else {
+                    arguments.push(self.expression()?);
+                }
 
                 if !self.match_token_type(&[TokenType::Comma]) {
                     break;
@@ -390,7 +942,7 @@ impl Parser {
 
         let paren = self.consume(TokenType::RightParen, "Expected ')' after arguments.")?;
 
-        Ok(Expr::Call(Box::new(callee), paren.clone(), arguments))
+        Ok(Expr::Call(Box::new(callee), paren.clone(), arguments, keywords))
     }
 
     pub fn primary(&mut self) -> Result<Expr, ParseError> {
@@ -416,6 +968,56 @@ impl Parser {
             return Ok(Expr::Literal(Literal::Null));
         }
 
+        if self.match_token_type(&[TokenType::If]) {
+            return self.if_expression();
+        }
+
+        if self.match_token_type(&[TokenType::Fn]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::LeftParen, "Expected '(' after 'fn'")?;
+            let (parameters, rest, body) = self.function_rest("function")?;
+            return Ok(Expr::Lambda(Box::new(LambdaDecl {
+                keyword,
+                parameters,
+                rest,
+                body,
+            })));
+        }
+
+        if self.match_token_type(&[TokenType::LeftBracket]) {
+            let mut elements = Vec::new();
+            if !self.check(&TokenType::RightBracket) {
+                loop {
+                    elements.push(self.expression()?);
+
+                    if !self.match_token_type(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(TokenType::RightBracket, "Expected ']' after array elements.")?;
+            return Ok(Expr::Array(elements));
+        }
+
+        // a '{' that starts a statement is a block, anywhere else it starts a map
+        if self.match_token_type(&[TokenType::LeftBrace]) {
+            let brace = self.previous().clone();
+            let mut entries = Vec::new();
+            if !self.check(&TokenType::RightBrace) {
+                loop {
+                    let key = self.expression()?;
+                    self.consume(TokenType::Colon, "Expected ':' after map key.")?;
+                    entries.push((key, self.expression()?));
+
+                    if !self.match_token_type(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expected '}' after map entries.")?;
+            return Ok(Expr::Map(brace, entries));
+        }
+
         if self.match_token_type(&[TokenType::Identifier]) {
             return Ok(Expr::Variable(self.previous().clone()));
         }
@@ -426,6 +1028,19 @@ impl Parser {
         });
     }
 
+    pub fn if_expression(&mut self) -> Result<Expr, ParseError> {
+        self.consume(TokenType::LeftParen, "Expected '(' before expression.")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expected ')' after expression.")?;
+
+        let then_expr = self.expression()?;
+
+        self.consume(TokenType::Else, "Expected 'else' branch in an 'if' expression.")?;
+        let else_expr = self.expression()?;
+
+        Ok(Expr::If(Box::new(condition), Box::new(then_expr), Box::new(else_expr)))
+    }
+
     pub fn match_token_type(&mut self, token_types: &[TokenType]) -> bool {
         for token_type in token_types {
             if self.check(token_type) {
@@ -445,14 +1060,40 @@ impl Parser {
         self.peek().token_type == *token_type
     }
 
+    pub fn check_next(&self, token_type: &TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == *token_type,
+            None => false,
+        }
+    }
+
     pub fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
+            self.fill();
         }
 
         return self.previous();
     }
 
+    /// Pull tokens from the lexer until the current token and the one after it are
+    /// buffered, discarding tokens before the previous one.
+    fn fill(&mut self) {
+        if let Some(lexer) = &mut self.lexer {
+            if self.current > 1 {
+                self.tokens.drain(..self.current - 1);
+                self.current = 1;
+            }
+
+            while self.tokens.len() <= self.current + 1 {
+                match lexer.next_token() {
+                    Some(token) => self.tokens.push(token),
+                    None => break,
+                }
+            }
+        }
+    }
+
     pub fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, ParseError> {
         if self.check(&token_type) {
             Ok(self.advance())
@@ -464,6 +1105,25 @@ impl Parser {
         }
     }
 
+    /// Consume the ';' ending a statement, or accept its absence at a line break in auto-semicolon mode.
+    pub fn consume_terminator(&mut self, message: &str) -> Result<(), ParseError> {
+        if self.match_token_type(&[TokenType::Semicolon]) || self.at_implicit_terminator() {
+            Ok(())
+        } else {
+            Err(ParseError {
+                token: self.peek().clone(),
+                message: message.to_string(),
+            })
+        }
+    }
+
+    fn at_implicit_terminator(&self) -> bool {
+        self.auto_semicolon
+            && (self.is_at_end()
+                || self.check(&TokenType::RightBrace)
+                || self.peek().line > self.previous().line)
+    }
+
     pub fn is_at_end(&self) -> bool {
         self.peek().token_type == TokenType::EOF
     }
@@ -476,3 +1136,62 @@ impl Parser {
         &self.tokens[self.current - 1]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_parser_over_a_lexer_holds_only_the_tokens_it_is_looking_at() {
+        let source = "let x = 1 + 2 * 3;\n".repeat(100_000);
+        let mut parser = Parser::from_lexer(Lexer::new(&source));
+
+        for _ in 0..1000 {
+            parser.declaration().expect("each statement should parse");
+            assert!(parser.tokens.len() <= 3, "{} tokens buffered", parser.tokens.len());
+        }
+
+        // the rest of the program is still waiting in the lexer
+        assert!(!parser.is_at_end());
+        assert_eq!(parser.parse().expect("the rest should parse").len(), 99_000);
+    }
+
+    fn parse_with_max_arguments(source: &str, max_arguments: usize) -> Result<Vec<Stmt>, ParseError> {
+        let mut parser = Parser::from_lexer(Lexer::new(source));
+        parser.set_max_arguments(max_arguments);
+        parser.parse()
+    }
+
+    #[test]
+    fn too_many_parameters_or_arguments_stop_the_parse() {
+        let declaration = parse_with_max_arguments("fn f(a, b, c) {}", 2).unwrap_err();
+        assert_eq!(declaration.message, "Can't have more than 2 parameters.");
+        assert_eq!(&*declaration.token.lexeme, "c");
+
+        let call = parse_with_max_arguments("f(1, 2, 3);", 2).unwrap_err();
+        assert_eq!(call.message, "Can't have more than 2 arguments.");
+
+        assert!(parse_with_max_arguments("fn f(a, b) {} f(1, 2);", 2).is_ok());
+        assert!(parse_with_max_arguments(&format!("f({});", ["1"; 255].join(", ")), DEFAULT_MAX_ARGUMENTS).is_ok());
+    }
+
+    #[test]
+    fn an_else_if_chain_is_one_if_with_an_arm_per_condition() {
+        let source = "if (a) print 1; else if (b) print 2; else if (c) print 3; else print 4;";
+        let stmts = Parser::from_lexer(Lexer::new(source)).parse().expect("source should parse");
+
+        let [Stmt::If(arms, else_branch)] = &stmts[..] else {
+            panic!("expected a single if, got {:?}", stmts);
+        };
+        let conditions: Vec<&str> = arms
+            .iter()
+            .map(|(condition, _)| match condition {
+                Expr::Variable(name) => &*name.lexeme,
+                other => panic!("expected a variable, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(conditions, ["a", "b", "c"]);
+        assert!(arms.iter().all(|(_, branch)| matches!(branch, Stmt::Print(..))));
+        assert!(matches!(**else_branch, Stmt::Print(_, Expr::Literal(Literal::Number(x))) if x == 4.0));
+    }
+}
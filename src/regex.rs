@@ -0,0 +1,579 @@
+/// A small regular expression engine for the `regex_match` and `regex_replace` natives.
+///
+/// roz builds with the standard library alone, so instead of using the `regex` crate a
+/// pattern is compiled here into a program for a Pike VM. That runs in time proportional to
+/// the length of the text times the size of the program whatever the pattern, and doesn't
+/// recurse, so neither a pattern like `(a|a)*b` nor a long text can hang or overflow the stack.
+///
+/// Supported syntax: literals, `.`, character classes like `[a-z_]` and `[^0-9]`,
+/// the escapes `\d \w \s \D \W \S \b \B \n \t \r` and escaped punctuation, the anchors
+/// `^` and `$`, groups `(...)` and non-capturing groups `(?:...)`, alternation `|`,
+/// and the quantifiers `* + ? {n} {n,} {n,m}`, each of which can be made lazy with a
+/// trailing `?`. Matching works on characters, not bytes.
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+    groups: usize, // number of capturing groups, not counting the whole match
+}
+
+// the most instructions a pattern may compile to, as counted repetitions are written out
+const MAX_PROGRAM: usize = 100_000;
+
+// the largest count allowed in `{n}`, `{n,}` and `{n,m}`
+const MAX_REPEAT: usize = 1000;
+
+// how deeply groups may nest, since a pattern is parsed and compiled by recursion
+const MAX_NESTING: usize = 200;
+
+/// The span of each group of a match, as character positions, with the whole match first.
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+#[derive(Debug)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool), // items, whether the class is negated
+    Start,
+    End,
+    WordBoundary(bool), // whether it is `\b` rather than `\B`
+    Group(Box<Node>, Option<usize>), // inner node, capture index
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat(Box<Node>, usize, Option<usize>, bool), // node, min, max, greedy
+}
+
+/// One step of a compiled pattern. The first three consume a character, the rest don't.
+#[derive(Debug)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    Start,
+    End,
+    WordBoundary(bool),
+    Split(usize, usize), // carry on at both, preferring the first
+    Jump(usize),
+    Save(usize), // note the position in a capture slot
+    Match,
+}
+
+impl Inst {
+    fn consumes(&self, c: char) -> bool {
+        match self {
+            Inst::Char(expected) => c == *expected,
+            Inst::Any => c != '\n',
+            Inst::Class(items, negated) => items.iter().any(|item| item.matches(c)) != *negated,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool), // whether it is `\d` rather than `\D`
+    Word(bool),
+    Space(bool),
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            ClassItem::Range(low, high) => (*low..=*high).contains(&c),
+            ClassItem::Digit(positive) => c.is_ascii_digit() == *positive,
+            ClassItem::Word(positive) => is_word(c) == *positive,
+            ClassItem::Space(positive) => c.is_whitespace() == *positive,
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl Regex {
+    /// Compile a pattern, describing what is wrong with it if it is invalid.
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let mut parser = PatternParser {
+            chars: pattern.chars().collect(),
+            current: 0,
+            groups: 0,
+            depth: 0,
+        };
+
+        let node = parser.alternation()?;
+
+        if parser.current < parser.chars.len() {
+            // the only thing that stops an alternation early is an unopened `)`
+            return Err("Unmatched ')' in pattern.".to_string());
+        }
+
+        // slots 0 and 1 hold where the whole match starts and ends
+        let mut compiler = Compiler { program: Vec::new() };
+        compiler.emit(Inst::Save(0))?;
+        compiler.node(&node)?;
+        compiler.emit(Inst::Save(1))?;
+        compiler.emit(Inst::Match)?;
+
+        Ok(Regex {
+            program: compiler.program,
+            groups: parser.groups,
+        })
+    }
+
+    /// Find the first match in `text` that starts at or after `from`.
+    ///
+    /// Every thread of the program moves one character along the text at a time, kept in
+    /// order of preference, so the first to reach `Match` is the one a backtracking engine
+    /// would have found, and a position needs looking at only once for each instruction.
+    pub fn captures(&self, text: &[char], from: usize) -> Option<Captures> {
+        let slots = 2 * (self.groups + 1);
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+
+        for position in from..=text.len() {
+            // a match may start here, unless one starting further left has been found
+            if matched.is_none() {
+                self.add_thread(&mut current, 0, vec![None; slots], text, position);
+            }
+
+            for (pc, slots) in current.list.drain(..) {
+                match &self.program[pc] {
+                    Inst::Match => {
+                        // the threads after this one are less preferred, so they can go
+                        matched = Some(slots);
+                        break;
+                    }
+                    inst => {
+                        if text.get(position).is_some_and(|c| inst.consumes(*c)) {
+                            self.add_thread(&mut next, pc + 1, slots, text, position + 1);
+                        }
+                    }
+                }
+            }
+
+            std::mem::swap(&mut current, &mut next);
+
+            if matched.is_some() && current.list.is_empty() {
+                break;
+            }
+        }
+
+        matched.map(|slots| {
+            slots
+                .chunks(2)
+                .map(|span| match span {
+                    [Some(start), Some(end)] => Some((*start, *end)),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    /// Add a thread at `pc` to `threads`, first following every jump, split, save and
+    /// anchor from there so that only threads waiting on a character are kept.
+    fn add_thread(&self, threads: &mut Threads, pc: usize, slots: Slots, text: &[char], position: usize) {
+        let mut stack = vec![(pc, slots)];
+
+        while let Some((pc, mut slots)) = stack.pop() {
+            // a thread that got here first was preferred, and would go the same way from here
+            if threads.seen[pc] == Some(position) {
+                continue;
+            }
+            threads.seen[pc] = Some(position);
+
+            match &self.program[pc] {
+                Inst::Jump(to) => stack.push((*to, slots)),
+                Inst::Split(first, second) => {
+                    stack.push((*second, slots.clone()));
+                    stack.push((*first, slots));
+                }
+                Inst::Save(slot) => {
+                    slots[*slot] = Some(position);
+                    stack.push((pc + 1, slots));
+                }
+                Inst::Start => {
+                    if position == 0 {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::End => {
+                    if position == text.len() {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                Inst::WordBoundary(positive) => {
+                    let before = position > 0 && is_word(text[position - 1]);
+                    let after = position < text.len() && is_word(text[position]);
+                    if (before != after) == *positive {
+                        stack.push((pc + 1, slots));
+                    }
+                }
+                _ => threads.list.push((pc, slots)),
+            }
+        }
+    }
+}
+
+/// Where each capture slot was last set, two slots to a group: its start and its end.
+type Slots = Vec<Option<usize>>;
+
+/// The threads at one position in the text, most preferred first.
+struct Threads {
+    list: Vec<(usize, Slots)>,
+    seen: Vec<Option<usize>>, // for each instruction, the last position a thread reached it at
+}
+
+impl Threads {
+    fn new(size: usize) -> Self {
+        Threads {
+            list: Vec::new(),
+            seen: vec![None; size],
+        }
+    }
+}
+
+/// Turns a tree of nodes into a program, writing counted repetitions out in full.
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.program.len() >= MAX_PROGRAM {
+            return Err("Pattern is too large.".to_string());
+        }
+
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Char(c) => {
+                self.emit(Inst::Char(*c))?;
+            }
+            Node::Any => {
+                self.emit(Inst::Any)?;
+            }
+            Node::Class(items, negated) => {
+                self.emit(Inst::Class(items.clone(), *negated))?;
+            }
+            Node::Start => {
+                self.emit(Inst::Start)?;
+            }
+            Node::End => {
+                self.emit(Inst::End)?;
+            }
+            Node::WordBoundary(positive) => {
+                self.emit(Inst::WordBoundary(*positive))?;
+            }
+            Node::Group(inner, None) => self.node(inner)?,
+            Node::Group(inner, Some(index)) => {
+                self.emit(Inst::Save(2 * index))?;
+                self.node(inner)?;
+                self.emit(Inst::Save(2 * index + 1))?;
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.node(node)?;
+                }
+            }
+            Node::Alternation(branches) => {
+                // each branch but the last is tried before moving on to the next, and jumps
+                // past the others once it has matched
+                let mut jumps = Vec::new();
+
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 == branches.len() {
+                        self.node(branch)?;
+                        break;
+                    }
+
+                    let split = self.emit(Inst::Split(0, 0))?;
+                    self.node(branch)?;
+                    jumps.push(self.emit(Inst::Jump(0))?);
+                    self.program[split] = Inst::Split(split + 1, self.program.len());
+                }
+
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(self.program.len());
+                }
+            }
+            Node::Repeat(inner, min, max, greedy) => {
+                for _ in 0..*min {
+                    self.node(inner)?;
+                }
+
+                match max {
+                    Some(max) => {
+                        // skipping an optional copy skips the ones after it as well
+                        let mut splits = Vec::new();
+
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0))?);
+                            self.node(inner)?;
+                        }
+
+                        for split in splits {
+                            self.program[split] = repeat_split(split + 1, self.program.len(), *greedy);
+                        }
+                    }
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.node(inner)?;
+                        self.emit(Inst::Jump(split))?;
+                        self.program[split] = repeat_split(split + 1, self.program.len(), *greedy);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A split between going round a repetition again and leaving it, with a greedy repetition
+/// preferring to go round and a lazy one to leave.
+fn repeat_split(again: usize, leave: usize, greedy: bool) -> Inst {
+    if greedy {
+        Inst::Split(again, leave)
+    } else {
+        Inst::Split(leave, again)
+    }
+}
+
+/// Turns a pattern into a tree of nodes by recursive descent.
+struct PatternParser {
+    chars: Vec<char>,
+    current: usize,
+    groups: usize,
+    depth: usize, // how many groups the parser is inside
+}
+
+impl PatternParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.current += 1;
+        c
+    }
+
+    fn match_char(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concat()?];
+
+        while self.match_char('|') {
+            branches.push(self.concat()?);
+        }
+
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternation(branches))
+        }
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+
+            let atom = self.atom()?;
+            nodes.push(self.quantifier(atom)?);
+        }
+
+        Ok(Node::Concat(nodes))
+    }
+
+    fn quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = if self.match_char('*') {
+            (0, None)
+        } else if self.match_char('+') {
+            (1, None)
+        } else if self.match_char('?') {
+            (0, Some(1))
+        } else if self.match_char('{') {
+            self.repetition()?
+        } else {
+            return Ok(atom);
+        };
+
+        if matches!(atom, Node::Start | Node::End | Node::WordBoundary(_)) {
+            return Err("Can't repeat an anchor.".to_string());
+        }
+
+        let greedy = !self.match_char('?');
+
+        Ok(Node::Repeat(Box::new(atom), min, max, greedy))
+    }
+
+    /// Parse the inside of `{n}`, `{n,}` or `{n,m}` after the opening brace.
+    fn repetition(&mut self) -> Result<(usize, Option<usize>), String> {
+        let min = self.number().ok_or("Expected a number after '{' in pattern.")?;
+
+        let max = if self.match_char(',') {
+            self.number()
+        } else {
+            Some(min)
+        };
+
+        if !self.match_char('}') {
+            return Err("Expected '}' to close the repetition in pattern.".to_string());
+        }
+
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("Invalid repetition {{{},{}}} in pattern.", min, max.unwrap()));
+        }
+
+        if max.unwrap_or(min) > MAX_REPEAT {
+            return Err(format!("Can't repeat more than {} times in pattern.", MAX_REPEAT));
+        }
+
+        Ok((min, max))
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.current;
+
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.advance();
+        }
+
+        self.chars[start..self.current].iter().collect::<String>().parse().ok()
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.advance() {
+            Some('(') => {
+                if self.depth == MAX_NESTING {
+                    return Err("Groups are nested too deeply in pattern.".to_string());
+                }
+
+                let index = if self.match_char('?') {
+                    if !self.match_char(':') {
+                        return Err("Only '(?:' groups are supported in patterns.".to_string());
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+
+                self.depth += 1;
+                let inner = self.alternation()?;
+                self.depth -= 1;
+
+                if !self.match_char(')') {
+                    return Err("Unclosed '(' in pattern.".to_string());
+                }
+
+                Ok(Node::Group(Box::new(inner), index))
+            }
+            Some('[') => self.class(),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('\\') => self.escape(),
+            Some(c @ ('*' | '+' | '?' | '{')) => Err(format!("Nothing to repeat before '{}' in pattern.", c)),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("Unexpected end of pattern.".to_string()),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        match self.advance() {
+            Some('b') => Ok(Node::WordBoundary(true)),
+            Some('B') => Ok(Node::WordBoundary(false)),
+            Some(_) => {
+                self.current -= 1;
+                match self.class_escape()? {
+                    ClassItem::Range(c, _) => Ok(Node::Char(c)),
+                    item => Ok(Node::Class(vec![item], false)),
+                }
+            }
+            None => Err("Pattern ends with a lone '\\'.".to_string()),
+        }
+    }
+
+    /// Parse the character after a `\`, as it would appear in a class.
+    fn class_escape(&mut self) -> Result<ClassItem, String> {
+        let c = match self.advance() {
+            Some('d') => return Ok(ClassItem::Digit(true)),
+            Some('D') => return Ok(ClassItem::Digit(false)),
+            Some('w') => return Ok(ClassItem::Word(true)),
+            Some('W') => return Ok(ClassItem::Word(false)),
+            Some('s') => return Ok(ClassItem::Space(true)),
+            Some('S') => return Ok(ClassItem::Space(false)),
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some(c) if !c.is_alphanumeric() => c,
+            Some(c) => return Err(format!("Unknown escape '\\{}' in pattern.", c)),
+            None => return Err("Pattern ends with a lone '\\'.".to_string()),
+        };
+
+        Ok(ClassItem::Range(c, c))
+    }
+
+    /// Parse a class after its opening bracket. A `]` right at the start is taken literally.
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.match_char('^');
+        let mut items = Vec::new();
+
+        loop {
+            let low = match self.advance() {
+                None => return Err("Unclosed '[' in pattern.".to_string()),
+                Some(']') if !items.is_empty() => break,
+                Some('\\') => match self.class_escape()? {
+                    ClassItem::Range(c, _) => c,
+                    item => {
+                        items.push(item);
+                        continue;
+                    }
+                },
+                Some(c) => c,
+            };
+
+            let is_range = self.peek() == Some('-') && !matches!(self.chars.get(self.current + 1), Some(']') | None);
+
+            if !is_range {
+                items.push(ClassItem::Range(low, low));
+                continue;
+            }
+
+            self.advance();
+            let high = match self.advance() {
+                Some('\\') => match self.class_escape()? {
+                    ClassItem::Range(c, _) => c,
+                    _ => return Err("A class shorthand can't end a range in pattern.".to_string()),
+                },
+                Some(c) => c,
+                None => return Err("Unclosed '[' in pattern.".to_string()),
+            };
+
+            if high < low {
+                return Err(format!("Invalid range '{}-{}' in pattern.", low, high));
+            }
+
+            items.push(ClassItem::Range(low, high));
+        }
+
+        Ok(Node::Class(items, negated))
+    }
+}
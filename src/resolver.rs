@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+
+use crate::{
+    lexer::{Token, TokenType},
+    literal::Literal,
+    roz,
+    stmt::{Expr, Stmt},
+};
+
+/// A static pass over the parsed program that runs before interpretation and
+/// reports mistakes that can be detected without executing any code.
+pub struct Resolver {
+    // Each local scope maps a name to whether its initializer has been resolved.
+    // The global scope is not tracked, so top-level redeclaration stays legal.
+    scopes: Vec<HashMap<String, bool>>,
+    strict: bool,   // whether suspicious code is an error rather than a warning
+    loops: Vec<Option<String>>, // labels of the loops around the current statement within its function
+    in_function: bool,  // whether the current statement is inside a function body, where `return` is allowed
+    implicit_return: bool,  // whether a function's last expression statement is its return value
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            strict: false,
+            loops: Vec::new(),
+            in_function: false,
+            implicit_return: false,
+        }
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Match the interpreter's `set_implicit_return`, so a function ending in an expression
+    /// statement isn't taken to fall off its end.
+    pub fn set_implicit_return(&mut self, implicit_return: bool) {
+        self.implicit_return = implicit_return;
+    }
+
+    pub fn resolve(&mut self, stmts: &[Stmt]) {
+        let mut reachable = true;
+
+        for stmt in stmts {
+            if !reachable {
+                // only the first dead statement is flagged, the rest follow from it
+                if let Some(token) = stmt.token() {
+                    self.suspicious(token, "Unreachable code.");
+                }
+                reachable = true;
+            }
+
+            self.resolve_stmt(stmt);
+
+            if Self::always_exits(stmt) {
+                reachable = false;
+            }
+        }
+    }
+
+    /// Whether running `stmt` always leaves the enclosing block, through a `return`, `break`
+    /// or `continue` on every path. Loops are assumed to fall through, and a switch only exits
+    /// when it has a `default` and every arm exits.
+    fn always_exits(stmt: &Stmt) -> bool {
+        match stmt {
+            Stmt::Return(..) | Stmt::Break(..) | Stmt::Continue(..) => true,
+            Stmt::Block(stmts) => stmts.iter().any(Self::always_exits),
+            Stmt::If(arms, else_branch) => {
+                arms.iter().all(|(_, branch)| Self::always_exits(branch)) && Self::always_exits(else_branch)
+            }
+            Stmt::Switch(_, cases, Some(default)) => cases
+                .iter()
+                .map(|(_, stmts)| stmts)
+                .chain([default])
+                .all(|stmts| stmts.iter().any(Self::always_exits)),
+            _ => false,
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => self.resolve_expr(expr),
+            Stmt::Print(_, expr) => self.resolve_expr(expr),
+            Stmt::If(arms, else_stmt) => {
+                for (i, (condition, branch)) in arms.iter().enumerate() {
+                    self.check_condition(condition);
+                    self.check_constant_condition(condition, branch, &arms[i + 1..], else_stmt);
+                    self.resolve_expr(condition);
+                    self.resolve_stmt(branch);
+                }
+                self.resolve_stmt(else_stmt);
+            }
+            Stmt::While(condition, body, increment, else_branch, label) => {
+                self.check_condition(condition);
+                self.resolve_expr(condition);
+                self.resolve_loop_body(body, label);
+                self.resolve_expr(increment);
+                self.resolve_stmt(else_branch);
+            }
+            Stmt::Repeat(_, count, body, label) => {
+                self.resolve_expr(count);
+                self.resolve_loop_body(body, label);
+            }
+            Stmt::DoWhile(body, condition, label) => {
+                self.resolve_loop_body(body, label);
+                self.check_condition(condition);
+                self.resolve_expr(condition);
+            }
+            Stmt::Switch(subject, cases, default) => {
+                self.resolve_expr(subject);
+                for (value, stmts) in cases {
+                    self.resolve_expr(value);
+                    self.begin_scope();
+                    self.resolve(stmts);
+                    self.end_scope();
+                }
+                if let Some(stmts) = default {
+                    self.begin_scope();
+                    self.resolve(stmts);
+                    self.end_scope();
+                }
+            }
+            Stmt::Var(name, initializer) | Stmt::Const(name, initializer) => {
+                self.declare(name);
+                self.resolve_expr(initializer);
+                self.define(name);
+            }
+            Stmt::LetElse(name, initializer, else_branch) => {
+                self.declare(name);
+                self.resolve_expr(initializer);
+                // the name isn't bound yet while the else branch runs
+                self.resolve_stmt(else_branch);
+                self.define(name);
+
+                if !Self::always_exits(else_branch) {
+                    let message = "The else branch of 'let ... else' must leave with return, break or continue.";
+                    roz::error(name, message);
+                }
+            }
+            Stmt::VarMulti(declarations) => {
+                for (name, initializer) in declarations {
+                    self.declare(name);
+                    self.resolve_expr(initializer);
+                    self.define(name);
+                }
+            }
+            Stmt::Destructure(_, names, rest, initializer) => {
+                for name in names.iter().chain(rest) {
+                    self.declare(name);
+                }
+                self.resolve_expr(initializer);
+                for name in names.iter().chain(rest) {
+                    self.define(name);
+                }
+            }
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                self.resolve(stmts);
+                self.end_scope();
+            }
+            Stmt::Function(name, parameters, rest, body) => {
+                self.declare(name);
+                self.define(name);
+                self.resolve_function(name, parameters, rest, body);
+            }
+            Stmt::Return(keyword, value) => {
+                if !self.in_function {
+                    roz::error(keyword, "Can't return from top-level code.");
+                }
+                self.resolve_expr(value);
+            }
+            Stmt::Break(keyword, label) | Stmt::Continue(keyword, label) => {
+                self.resolve_loop_control(keyword, label);
+            }
+            Stmt::None => (),
+        }
+    }
+
+    fn resolve_loop_body(&mut self, body: &Stmt, label: &Option<Token>) {
+        self.loops.push(label.as_ref().map(|label| label.lexeme.to_string()));
+        self.resolve_stmt(body);
+        self.loops.pop();
+    }
+
+    /// Check that a `break` or `continue` has a loop to act on.
+    fn resolve_loop_control(&mut self, keyword: &Token, label: &Option<Token>) {
+        if self.loops.is_empty() {
+            let message = format!("Can't use '{}' outside of a loop.", keyword.lexeme);
+            roz::error(keyword, &message);
+        } else if let Some(label) = label {
+            if !self.loops.contains(&Some(label.lexeme.to_string())) {
+                let message = format!("No enclosing loop is labeled '{}'.", label.lexeme);
+                roz::error(label, &message);
+            }
+        }
+    }
+
+    fn resolve_function(
+        &mut self,
+        name: &Token,
+        parameters: &[(Token, Option<Expr>)],
+        rest: &Option<Token>,
+        body: &Stmt,
+    ) {
+        // Default values are evaluated in the environment of the caller.
+        for (_, default) in parameters {
+            if let Some(default) = default {
+                self.resolve_expr(default);
+            }
+        }
+
+        // A loop around the declaration doesn't make `break` valid inside the body.
+        let loops = std::mem::take(&mut self.loops);
+        let in_function = std::mem::replace(&mut self.in_function, true);
+
+        // The parameters and the body share a single scope, as in `Function::call`.
+        self.begin_scope();
+        for (parameter, _) in parameters {
+            self.declare(parameter);
+            self.define(parameter);
+        }
+        if let Some(rest) = rest {
+            self.declare(rest);
+            self.define(rest);
+        }
+        if let Some(stmts) = body.get_block_body() {
+            self.resolve(stmts);
+        }
+        self.end_scope();
+
+        // Only under `--strict`, and only as a warning, since loops are assumed to fall
+        // through and a loop that only ends by returning is reported too.
+        let ends_in_value = self.implicit_return
+            && matches!(body.get_block_body().and_then(|stmts| stmts.last()), Some(Stmt::Expression(_)));
+        if self.strict && Self::returns_value(body) && !Self::always_exits(body) && !ends_in_value {
+            let message = "Function returns a value on some paths but reaches the end without \
+                returning on others, where it returns nil.";
+            roz::warning(name, message);
+        }
+
+        self.loops = loops;
+        self.in_function = in_function;
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Logical(left, _, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Binary(left, operator, right) => {
+                self.check_division(left, operator, right);
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Unary(_, right) => self.resolve_expr(right),
+            Expr::Grouping(expr) => self.resolve_expr(expr),
+            Expr::Literal(_) => (),
+            Expr::Variable(name) => {
+                if let Some(scope) = self.scopes.last() {
+                    if scope.get(&*name.lexeme) == Some(&false) {
+                        roz::error(name, "Can't read local variable in its own initializer.");
+                    }
+                }
+            }
+            Expr::Assign(_, value) => self.resolve_expr(value),
+            Expr::Call(callee, _, arguments, keywords) => {
+                self.resolve_expr(callee);
+                for argument in arguments {
+                    self.resolve_expr(argument);
+                }
+                for (_, argument) in keywords {
+                    self.resolve_expr(argument);
+                }
+            }
+            Expr::Array(elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            Expr::Map(_, entries) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::If(condition, then_expr, else_expr) => {
+                self.resolve_expr(condition);
+                self.resolve_expr(then_expr);
+                self.resolve_expr(else_expr);
+            }
+            Expr::Lambda(lambda) => {
+                self.resolve_function(&lambda.keyword, &lambda.parameters, &lambda.rest, &lambda.body)
+            }
+            Expr::Get(object, _, _) => self.resolve_expr(object),
+            Expr::Index(object, _, index) => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            Expr::Slice(object, _, start, end) => {
+                self.resolve_expr(object);
+                self.resolve_expr(start);
+                self.resolve_expr(end);
+            }
+            Expr::SetIndex(object, _, index, value) => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            }
+            Expr::None => (),
+        }
+    }
+
+    /// Flag `if (x = 5)` and the like, which is almost always a typo for `==`.
+    /// Wrapping the assignment in another pair of parentheses marks it as intended.
+    fn check_condition(&mut self, condition: &Expr) {
+        if let Expr::Assign(name, _) = condition {
+            let message = "Assignment used as a condition, did you mean '=='? \
+                Wrap it in parentheses if the assignment is intended.";
+
+            self.suspicious(name, message);
+        }
+    }
+
+    /// Flag the branch of `if (true) ... else ...` or `if (false) ...` that can never run.
+    /// Only a warning even under `--strict`, since `if (false)` is a common way to turn code off.
+    fn check_constant_condition(&mut self, condition: &Expr, branch: &Stmt, later_arms: &[(Expr, Stmt)], else_stmt: &Stmt) {
+        let mut condition = condition;
+        while let Expr::Grouping(inner) = condition {
+            condition = inner;
+        }
+
+        let (dead, message) = match condition {
+            // everything after the arm is dead, starting from the next `else if` condition
+            Expr::Literal(Literal::Bool(true)) => (
+                later_arms.first().map_or(else_stmt.token(), |(next, _)| next.token()),
+                "Unreachable branch, the condition is always true.",
+            ),
+            Expr::Literal(Literal::Bool(false)) => {
+                (branch.token(), "Unreachable branch, the condition is always false.")
+            }
+            _ => return,
+        };
+
+        // an empty branch has nothing to point at, and nothing lost by not running
+        if let Some(token) = dead {
+            roz::warning(token, message);
+        }
+    }
+
+    /// Under `--strict`, flag `1 / 2` and the like, which gives 0.5 to someone who may have
+    /// expected the integer division `div` does. Numbers have a single type, so any literal
+    /// without a fractional part counts as an integer here.
+    fn check_division(&mut self, left: &Expr, operator: &Token, right: &Expr) {
+        if !self.strict || operator.token_type != TokenType::Slash {
+            return;
+        }
+
+        if let (Expr::Literal(Literal::Number(x)), Expr::Literal(Literal::Number(y))) = (left, right) {
+            if x.fract() == 0.0 && y.fract() == 0.0 && *y != 0.0 && (x / y).fract() != 0.0 {
+                let message = "Dividing two integers with '/' gives a fraction, use 'div' \
+                    for the integer quotient.";
+                roz::warning(operator, message);
+            }
+        }
+    }
+
+    /// Whether `stmt` has a `return` with a value, outside of any function nested in it.
+    fn returns_value(stmt: &Stmt) -> bool {
+        match stmt {
+            Stmt::Return(_, value) => *value != Expr::None,
+            Stmt::Block(stmts) => stmts.iter().any(Self::returns_value),
+            Stmt::If(arms, else_branch) => {
+                arms.iter().any(|(_, branch)| Self::returns_value(branch)) || Self::returns_value(else_branch)
+            }
+            Stmt::While(_, body, _, else_branch, _) => {
+                Self::returns_value(body) || Self::returns_value(else_branch)
+            }
+            Stmt::DoWhile(body, _, _) | Stmt::Repeat(_, _, body, _) => Self::returns_value(body),
+            Stmt::LetElse(_, _, else_branch) => Self::returns_value(else_branch),
+            Stmt::Switch(_, cases, default) => cases
+                .iter()
+                .map(|(_, stmts)| stmts)
+                .chain(default)
+                .flatten()
+                .any(Self::returns_value),
+            _ => false,
+        }
+    }
+
+    /// Report code that is legal but most likely a mistake: a warning, or an error under `--strict`.
+    fn suspicious(&mut self, token: &Token, message: &str) {
+        if self.strict {
+            roz::error(token, message);
+        } else {
+            roz::warning(token, message);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Mark a name as declared but not yet usable in the innermost scope.
+    fn declare(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&*name.lexeme) {
+                roz::error(name, "Already a variable with this name in this scope.");
+            }
+            scope.insert(name.lexeme.to_string(), false);
+        }
+    }
+
+    /// Mark a declared name as fully initialized and available for use.
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.to_string(), true);
+        }
+    }
+}
@@ -1,17 +1,117 @@
+use std::env;
+use std::ffi::OsStr;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Read, Write};
+use std::panic;
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::{
-    interpreter::{Interpreter, RuntimeError, RuntimeException},
+    formatter,
+    interpreter::{Interpreter, RuntimeError, RuntimeErrorKind, RuntimeException},
     lexer::{Lexer, Token, TokenType},
+    literal::Literal,
     parser::Parser,
+    resolver::Resolver,
 };
 
 static mut HAD_ERROR: bool = false;
 static mut HAD_RUNTIME_ERROR: bool = false;
 
-pub fn run_prompt() {
+// the source being run, kept around to quote offending lines in error messages
+static SOURCE: Mutex<String> = Mutex::new(String::new());
+
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// The stack a program should be run with: room for the tallest expression the parser
+/// accepts and the deepest calls the interpreter makes, even in a debug build, where each
+/// level of them takes several kilobytes.
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// how many columns apart tab stops are when lining up carets under source lines
+static TAB_WIDTH: AtomicUsize = AtomicUsize::new(DEFAULT_TAB_WIDTH);
+
+// problems found since they were last rendered, see `take_diagnostics`
+static DIAGNOSTICS: Mutex<Vec<Diagnostic>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,          // found before running, so the program doesn't run
+    Warning,        // found before running, but the program runs anyway
+    RuntimeError,   // stopped the program while it ran
+}
+
+impl Severity {
+    pub fn to_string(&self) -> String {
+        match self {
+            Severity::Error => "error".to_string(),
+            Severity::Warning => "warning".to_string(),
+            Severity::RuntimeError => "runtime_error".to_string(),
+        }
+    }
+}
+
+/// A problem with the program being run, kept as data so it can be rendered for a
+/// person or handed to a tool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: Option<RuntimeErrorKind>, // set for a runtime error only
+    pub line: usize,
+    pub column: usize,          // counting every character as one, tabs included
+    pub display_column: usize,  // where `column` appears on screen, with tabs reaching the next tab stop
+    pub message: String,
+    pub location: String,       // like "at 'x'" or "at the end", empty when the position says it all
+    pub trace: Vec<(String, usize)>, // for a runtime error, the functions it passed through and the lines they were called from, innermost last
+}
+
+/// Settings chosen on the command line that change how a program is run.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub auto_semicolon: bool,
+    pub strict: bool,
+    pub sandbox: bool,
+    pub time: bool,
+    pub strict_truthiness: bool,
+    pub boolean_logical: bool,
+    pub implicit_return: bool,
+    pub json_diagnostics: bool,
+    pub json_diagnostics_file: Option<String>, // where the JSON array goes, stderr when not given
+    pub test: bool,
+    pub args: Vec<String>,  // the script path followed by the arguments given after it
+}
+
+impl Config {
+    /// A fresh interpreter, with or without access to the system as configured.
+    pub fn interpreter(&self) -> Interpreter {
+        let mut interpreter = if self.sandbox {
+            Interpreter::sandboxed()
+        } else {
+            Interpreter::new()
+        };
+
+        interpreter.set_args(self.args.clone());
+        interpreter.set_strict_truthiness(self.strict_truthiness);
+        interpreter.set_boolean_logical(self.boolean_logical);
+        interpreter.set_implicit_return(self.implicit_return);
+        interpreter.set_test_mode(self.test);
+        interpreter
+    }
+}
+
+pub fn run_prompt(config: &Config) {
+    // statements typed at the prompt don't need a trailing ';'
+    let mut config = config.clone();
+    config.auto_semicolon = true;
+
+    // one interpreter for the whole session so definitions carry over between lines,
+    // and an error in one statement doesn't keep the rest of the input from running
+    let mut interpreter = config.interpreter();
+    interpreter.set_continue_on_error(true);
+
     loop {
         print!("#> ");
         let mut input = String::new();
@@ -25,22 +125,148 @@ pub fn run_prompt() {
             break;
         }
 
-        run(&input);
+        if let Some(command) = input.trim().strip_prefix(':') {
+            meta_command(command, &config, &mut interpreter);
+        } else {
+            // keep reading until every opened bracket is closed, or the input ends
+            while is_incomplete(&input) {
+                print!("... ");
+                let _ = io::stdout().flush();
+
+                match io::stdin().read_line(&mut input) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => (),
+                }
+            }
+
+            run(&input, &config, &mut interpreter);
+        }
 
         unsafe {
             HAD_ERROR = false;
+            HAD_RUNTIME_ERROR = false;
         }
     }
 }
 
-pub fn run_file(filename: &str) -> ExitCode {
+/// Whether the input opens more parentheses, braces or brackets than it closes.
+fn is_incomplete(input: &str) -> bool {
+    let mut lexer = Lexer::new(input);
+    lexer.set_report_errors(false);
+
+    let mut depth = 0;
+    for token in lexer {
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace | TokenType::LeftBracket => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace | TokenType::RightBracket => depth -= 1,
+            _ => (),
+        }
+    }
+
+    depth > 0
+}
+
+/// Handle a REPL line starting with ':' (the ':' itself already stripped).
+fn meta_command(command: &str, config: &Config, interpreter: &mut Interpreter) {
+    let (name, argument) = match command.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, argument.trim()),
+        None => (command, ""),
+    };
+
+    match name {
+        "help" => {
+            println!(":help          show this list of commands");
+            println!(":env           show the variables defined in this session");
+            println!(":reset         forget everything defined in this session");
+            println!(":load <path>   run a file into this session");
+        }
+        "env" => interpreter.current_scope().display(),
+        "reset" => *interpreter = config.interpreter(),
+        "load" if argument.is_empty() => eprintln!("Usage: :load <path>"),
+        "load" => match fs::read_to_string(argument) {
+            Ok(filecontent) => run(&filecontent, config, interpreter),
+            Err(_) => eprintln!("Failed to read file {}", argument),
+        },
+        _ => eprintln!("Unknown command ':{}', type :help for a list of commands", name),
+    }
+}
+
+pub fn run_file(filename: &str, config: &Config) -> ExitCode {
     let filecontent = fs::read_to_string(filename).unwrap_or_else(|_| {
-        writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
+        eprintln!("Failed to read file {}", filename);
         String::new()
     });
 
-    run(&filecontent);
+    run(&filecontent, config, &mut config.interpreter());
+
+    exit_code()
+}
+
+/// Read a whole program from stdin and run it like a file.
+pub fn run_stdin(config: &Config) -> ExitCode {
+    let mut input = String::new();
+
+    if io::stdin().read_to_string(&mut input).is_err() {
+        eprintln!("Failed to read from stdin");
+        return ExitCode::from(74);
+    }
+
+    run(&input, config, &mut config.interpreter());
+
+    exit_code()
+}
+
+/// Print a file's program laid out in canonical form, without running it. The file
+/// itself is left alone, so the output can be checked before it replaces anything.
+pub fn format_file(filename: &str, config: &Config) -> ExitCode {
+    let filecontent = match fs::read_to_string(filename) {
+        Ok(filecontent) => filecontent,
+        Err(_) => {
+            eprintln!("Failed to read file {}", filename);
+            return ExitCode::from(66);
+        }
+    };
+
+    if let Ok(mut source) = SOURCE.lock() {
+        *source = filecontent.clone();
+    }
+
+    let mut lexer = Lexer::new(&filecontent);
+    lexer.scan_tokens();
+
+    // the formatter prints the tree, which has no comments, so rather than lose them
+    // a file with comments is left for the person to tidy
+    if let Some((line, column)) = lexer.first_comment() {
+        report(line, column, "", "Can't format a file with comments without losing them.");
+    }
+
+    // the lexer skips a `#!` first line, so it isn't in the tree either, but it is kept as it is
+    let shebang = filecontent.lines().next().filter(|line| line.starts_with("#!"));
+
+    let mut parser = Parser::new(std::mem::take(&mut lexer.tokens));
+    parser.set_auto_semicolon(config.auto_semicolon);
+
+    match parser.parse() {
+        Ok(stmts) if !unsafe { HAD_ERROR } => {
+            if let Some(shebang) = shebang {
+                println!("{}", shebang);
+            }
+            print!("{}", formatter::format(&stmts));
+        }
+        Ok(_) => (),
+        Err(parse_err) => error(&parse_err.token, &parse_err.message),
+    }
+
+    if config.json_diagnostics {
+        write_diagnostics_json(config);
+    } else {
+        render_diagnostics();
+    }
+
+    exit_code()
+}
 
+fn exit_code() -> ExitCode {
     unsafe {
         if HAD_ERROR {
             ExitCode::from(65)
@@ -52,63 +278,648 @@ pub fn run_file(filename: &str) -> ExitCode {
     }
 }
 
-pub fn run(input: &str) {
-    let mut lexer = Lexer::new(input);
-    lexer.scan_tokens();
+pub fn run(input: &str, config: &Config, interpreter: &mut Interpreter) {
+    let timings = run_timed(input, config, interpreter);
 
-    let mut parser = Parser::new(lexer.tokens);
-    let mut interpreter = Interpreter::new();
+    if config.json_diagnostics {
+        write_diagnostics_json(config);
+    } else {
+        render_diagnostics();
+    }
 
-    match parser.parse() {
-        Ok(stmts) => {
-            unsafe {
-                if HAD_ERROR {
-                    return;
-                }
+    if config.time {
+        timings.report();
+    }
+
+    if config.test {
+        match interpreter.assertions() {
+            (passed, 0) => println!("{} assertions passed", passed),
+            (passed, failed) => println!("{} assertions passed, {} failed", passed, failed),
+        }
+    }
+}
+
+/// How long each phase of running a program took.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    pub lex: Duration,
+    pub parse: Duration,
+    pub resolve: Duration,
+    pub interpret: Duration,
+}
+
+impl Timings {
+    /// Print the timings to stderr, where they don't mix with the program's output.
+    pub fn report(&self) {
+        eprintln!("lex:       {:?}", self.lex);
+        eprintln!("parse:     {:?}", self.parse);
+        eprintln!("resolve:   {:?}", self.resolve);
+        eprintln!("interpret: {:?}", self.interpret);
+    }
+}
+
+/// Run a program, measuring each phase. Phases that didn't run because an earlier one
+/// failed are left at zero.
+pub fn run_timed(input: &str, config: &Config, interpreter: &mut Interpreter) -> Timings {
+    if let Ok(mut source) = SOURCE.lock() {
+        *source = input.to_string();
+    }
+
+    let mut timings = Timings::default();
+    let mut start = Instant::now();
+
+    // the parser usually pulls tokens as it goes, but when timing, the whole source is
+    // lexed first so lexing isn't counted as parsing
+    let mut parser = if config.time {
+        let mut lexer = Lexer::new(input);
+        lexer.scan_tokens();
+        Parser::new(lexer.tokens)
+    } else {
+        Parser::from_lexer(Lexer::new(input))
+    };
+    parser.set_auto_semicolon(config.auto_semicolon);
+
+    timings.lex = start.elapsed();
+    start = Instant::now();
+
+    let stmts = match parser.parse() {
+        Ok(stmts) => stmts,
+        Err(parse_err) => {
+            error(&parse_err.token, &parse_err.message);
+            return timings;
+        }
+    };
+
+    timings.parse = start.elapsed();
+    start = Instant::now();
+
+    let mut resolver = Resolver::new();
+    resolver.set_strict(config.strict);
+    resolver.set_implicit_return(config.implicit_return);
+    resolver.resolve(&stmts);
+
+    timings.resolve = start.elapsed();
+    start = Instant::now();
+
+    unsafe {
+        if HAD_ERROR {
+            return timings;
+        }
+    }
+
+    // show any warnings before the program's own output
+    if !config.json_diagnostics {
+        render_diagnostics();
+    }
+
+    if let Err(runtime_exception) = interpreter.interpret(&stmts) {
+        match runtime_exception {
+            RuntimeException::Error(runtime_err) => runtime_error(runtime_err),
+            // the resolver keeps a top-level `return`, `break` or `continue`
+            // from getting this far
+            RuntimeException::Return(_)
+            | RuntimeException::Break(_)
+            | RuntimeException::Continue(_)
+            | RuntimeException::TailCall(_) => (),
+        }
+    }
+
+    timings.interpret = start.elapsed();
+    timings
+}
+
+/// Evaluate a source holding a single expression, like `2 * (3 + 4)`, in a fresh sandboxed
+/// interpreter. Problems are returned rather than reported, and don't count towards the
+/// exit code.
+pub fn eval_expr(input: &str) -> Result<Literal, Vec<Diagnostic>> {
+    let (had_error, had_runtime_error) = unsafe { (HAD_ERROR, HAD_RUNTIME_ERROR) };
+    let reported = DIAGNOSTICS.lock().map_or(0, |diagnostics| diagnostics.len());
+
+    if let Ok(mut source) = SOURCE.lock() {
+        *source = input.to_string();
+    }
+
+    let mut parser = Parser::from_lexer(Lexer::new(input));
+    let value = match parser.parse_expression() {
+        Ok(expr) if !unsafe { HAD_ERROR } => match Interpreter::sandboxed().evaluate(&expr) {
+            Ok(value) => Some(value),
+            Err(RuntimeException::Error(runtime_err)) => {
+                runtime_error(runtime_err);
+                None
             }
+            Err(_) => Some(Literal::Null),
+        },
+        Ok(_) => None,
+        Err(parse_err) => {
+            error(&parse_err.token, &parse_err.message);
+            None
+        }
+    };
+
+    let diagnostics = match DIAGNOSTICS.lock() {
+        Ok(mut diagnostics) => diagnostics.split_off(reported),
+        Err(_) => Vec::new(),
+    };
+
+    unsafe {
+        HAD_ERROR = had_error;
+        HAD_RUNTIME_ERROR = had_runtime_error;
+    }
+
+    value.ok_or(diagnostics)
+}
 
-            if let Err(runtime_exception) = interpreter.interpret(&stmts) {
-                match runtime_exception {
-                    RuntimeException::Error(runtime_err) => runtime_error(runtime_err),
-                    RuntimeException::Return(_) => (),
+/// Run arbitrary bytes as a program, for fuzzing: they are read as UTF-8, replacing invalid
+/// sequences, then lexed, parsed and resolved, and run in a sandboxed interpreter if
+/// `interpret` is set. A panic along the way is caught and returned as an error diagnostic,
+/// so a fuzzer can tell a crash from an input that is merely invalid. Like `eval_expr`,
+/// problems are returned rather than reported.
+///
+/// The input is run on a thread of its own with `STACK_SIZE` of stack, whatever the caller's
+/// stack is. A stack overflow aborts the process rather than panicking, so it can't be caught
+/// here; instead the parser bounds how tall a tree can get and the interpreter refuses calls
+/// past its depth limit, which between them keep any input within that stack.
+pub fn fuzz_run(data: &[u8], interpret: bool) -> Vec<Diagnostic> {
+    let (had_error, had_runtime_error) = unsafe { (HAD_ERROR, HAD_RUNTIME_ERROR) };
+    let reported = DIAGNOSTICS.lock().map_or(0, |diagnostics| diagnostics.len());
+    let input = String::from_utf8_lossy(data);
+
+    // the panic is reported as a diagnostic, so keep the default hook from printing it too
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let outcome = thread::scope(|scope| {
+        let run = || {
+            if interpret {
+                let config = Config {
+                    sandbox: true,
+                    json_diagnostics: true, // keeps `run_timed` from rendering warnings
+                    ..Config::default()
+                };
+                run_timed(&input, &config, &mut Interpreter::sandboxed());
+            } else {
+                match Parser::from_lexer(Lexer::new(&input)).parse() {
+                    Ok(stmts) => Resolver::new().resolve(&stmts),
+                    Err(parse_err) => error(&parse_err.token, &parse_err.message),
                 }
             }
+        };
+
+        match thread::Builder::new().stack_size(STACK_SIZE).spawn_scoped(scope, run) {
+            Ok(handle) => handle.join(),
+            Err(err) => Err(Box::new(format!("couldn't start a thread to run on: {}", err)) as Box<_>),
         }
-        Err(parse_err) => error(&parse_err.token, &parse_err.message),
+    });
+
+    panic::set_hook(hook);
+
+    if let Err(payload) = outcome {
+        let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+            (Some(message), _) => message.to_string(),
+            (_, Some(message)) => message.clone(),
+            _ => "unknown panic".to_string(),
+        };
+        report(0, 0, "", &format!("Internal error: {}", message));
+    }
+
+    let diagnostics = match DIAGNOSTICS.lock() {
+        Ok(mut diagnostics) => diagnostics.split_off(reported),
+        Err(_) => Vec::new(),
+    };
+
+    unsafe {
+        HAD_ERROR = had_error;
+        HAD_RUNTIME_ERROR = had_runtime_error;
+    }
+
+    diagnostics
+}
+
+/// Record an `assert` that failed under `--test`, which doesn't stop the program but
+/// does make it exit as if it had a runtime error. It is rendered as a `FAIL` line.
+pub fn assertion_failure(token: &Token, message: &str) {
+    push_diagnostic(Diagnostic {
+        severity: Severity::RuntimeError,
+        kind: Some(RuntimeErrorKind::AssertionFailed),
+        line: token.line,
+        column: token.column,
+        display_column: display_column(token.line, token.column),
+        message: message.to_string(),
+        location: String::new(),
+        trace: Vec::new(),
+    });
+
+    unsafe {
+        HAD_RUNTIME_ERROR = true;
     }
 }
 
-pub fn lexical_error(line: usize, message: &str) {
-    report(line, "", message);
+pub fn lexical_error(line: usize, column: usize, message: &str) {
+    report(line, column, "", message);
 }
 
 pub fn error(token: &Token, message: &str) {
     if token.token_type == TokenType::EOF {
-        report(token.line, "at the end", message);
+        report(token.line, token.column, "at the end", message);
     } else {
-        report(token.line, &format!("at '{}'", token.lexeme), message);
+        report(token.line, token.column, &format!("at '{}'", token.lexeme), message);
     }
 }
 
+pub fn warning(token: &Token, message: &str) {
+    push_diagnostic(Diagnostic {
+        severity: Severity::Warning,
+        kind: None,
+        line: token.line,
+        column: token.column,
+        display_column: display_column(token.line, token.column),
+        message: message.to_string(),
+        location: format!("at '{}'", token.lexeme),
+        trace: Vec::new(),
+    });
+}
+
 pub fn runtime_error(error: RuntimeError) {
-    writeln!(
-        io::stderr(),
-        "{}\n[line {}]",
-        error.message,
-        error.token.line
-    )
-    .unwrap();
+    push_diagnostic(Diagnostic {
+        severity: Severity::RuntimeError,
+        kind: Some(error.kind),
+        line: error.token.line,
+        column: error.token.column,
+        display_column: display_column(error.token.line, error.token.column),
+        message: error.message,
+        location: String::new(),
+        trace: error.trace.into_iter().map(|frame| (frame.name, frame.line)).collect(),
+    });
 
     unsafe {
         HAD_RUNTIME_ERROR = true;
     }
 }
 
-pub fn report(line: usize, whr: &str, message: &str) {
+pub fn report(line: usize, column: usize, whr: &str, message: &str) {
     // whr = where because where is a rust keyword
-    writeln!(io::stderr(), "[Line {}] Error {}: {}", line, whr, message).unwrap();
+    push_diagnostic(Diagnostic {
+        severity: Severity::Error,
+        kind: None,
+        line,
+        column,
+        display_column: display_column(line, column),
+        message: message.to_string(),
+        location: whr.to_string(),
+        trace: Vec::new(),
+    });
 
     unsafe {
         HAD_ERROR = true;
     }
 }
+
+/// Set how many columns apart tab stops are when showing where a problem is, so a caret
+/// lines up under tab-indented source. Widths below 1 are taken as 1.
+pub fn set_tab_width(width: usize) {
+    TAB_WIDTH.store(width.max(1), Ordering::Relaxed);
+}
+
+/// Expand the tabs in a line of source to spaces, up to the next tab stop each.
+fn expand_tabs(text: &str) -> String {
+    let width = TAB_WIDTH.load(Ordering::Relaxed);
+    let mut expanded = String::new();
+
+    for c in text.chars() {
+        if c == '\t' {
+            let spaces = width - expanded.chars().count() % width;
+            expanded.push_str(&" ".repeat(spaces));
+        } else {
+            expanded.push(c);
+        }
+    }
+
+    expanded
+}
+
+/// The column a character column of the source being run appears at once tabs are
+/// expanded.
+fn display_column(line: usize, column: usize) -> usize {
+    let source = match SOURCE.lock() {
+        Ok(source) => source,
+        Err(_) => return column,
+    };
+
+    match source.lines().nth(line.wrapping_sub(1)) {
+        Some(text) => {
+            let before: String = text.chars().take(column.saturating_sub(1)).collect();
+            // characters past the end of the line, like an EOF token's, still count one each
+            let past_end = column.saturating_sub(1).saturating_sub(text.chars().count());
+            expand_tabs(&before).chars().count() + past_end + 1
+        }
+        None => column,
+    }
+}
+
+fn push_diagnostic(diagnostic: Diagnostic) {
+    if let Ok(mut diagnostics) = DIAGNOSTICS.lock() {
+        diagnostics.push(diagnostic);
+    }
+}
+
+/// Remove and return the diagnostics reported since the last call, oldest first.
+pub fn take_diagnostics() -> Vec<Diagnostic> {
+    match DIAGNOSTICS.lock() {
+        Ok(mut diagnostics) => std::mem::take(&mut *diagnostics),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Print the diagnostics reported so far to stderr, for a person to read.
+fn render_diagnostics() {
+    for diagnostic in take_diagnostics() {
+        render(&diagnostic);
+    }
+}
+
+/// Write the pending diagnostics as a JSON array to the file the config names, or else
+/// to stderr, keeping them apart from what the program itself prints.
+fn write_diagnostics_json(config: &Config) {
+    let json = diagnostics_json(&take_diagnostics());
+
+    match &config.json_diagnostics_file {
+        Some(path) => {
+            if let Err(err) = fs::write(path, format!("{}\n", json)) {
+                eprintln!("Could not write diagnostics to '{}': {}", path, err);
+            }
+        }
+        None => eprintln!("{}", json),
+    }
+}
+
+/// Diagnostics as a JSON array of objects with the fields of `Diagnostic`, e.g.
+/// `[{"severity": "error", "line": 1, "column": 9, "display_column": 9, "message": "Expected ';'",
+/// "location": "at 'x'", "kind": null, "trace": []}]`. A runtime error has a kind like
+/// "division_by_zero", and its trace lists `{"function": name, "line": n}` objects.
+pub fn diagnostics_json(diagnostics: &[Diagnostic]) -> String {
+    let entries: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let trace: Vec<String> = diagnostic
+                .trace
+                .iter()
+                .map(|(name, line)| format!("{{\"function\": {}, \"line\": {}}}", json_string(name), line))
+                .collect();
+
+            let kind = match diagnostic.kind {
+                Some(kind) => json_string(&kind.to_string()),
+                None => "null".to_string(),
+            };
+
+            format!(
+                "{{\"severity\": {}, \"line\": {}, \"column\": {}, \"display_column\": {}, \"message\": {}, \"location\": {}, \"kind\": {}, \"trace\": [{}]}}",
+                json_string(&diagnostic.severity.to_string()),
+                diagnostic.line,
+                diagnostic.column,
+                diagnostic.display_column,
+                json_string(&diagnostic.message),
+                json_string(&diagnostic.location),
+                kind,
+                trace.join(", ")
+            )
+        })
+        .collect();
+
+    format!("[{}]", entries.join(", "))
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+fn render(diagnostic: &Diagnostic) {
+    let whr = if diagnostic.location.is_empty() {
+        String::new()
+    } else {
+        format!(" {}", diagnostic.location)
+    };
+
+    match diagnostic.severity {
+        Severity::Error => {
+            let location = paint(BOLD, &format!("[Line {}]", diagnostic.line));
+            let label = paint(RED, &format!("Error{}", whr));
+            eprintln!("{} {}: {}", location, label, diagnostic.message);
+        }
+        Severity::Warning => {
+            let location = paint(BOLD, &format!("[Line {}]", diagnostic.line));
+            let label = paint(YELLOW, "Warning");
+            eprintln!("{} {}{}: {}", location, label, whr, diagnostic.message);
+        }
+        Severity::RuntimeError if diagnostic.kind == Some(RuntimeErrorKind::AssertionFailed) => {
+            let location = paint(BOLD, &format!("[line {}]", diagnostic.line));
+            eprintln!("{} {}: {}", paint(RED, "FAIL"), location, diagnostic.message);
+        }
+        Severity::RuntimeError => {
+            let location = paint(BOLD, &format!("[line {}]", diagnostic.line));
+            eprintln!("{}\n{}", paint(RED, &diagnostic.message), location);
+        }
+    }
+
+    snippet(diagnostic.line, diagnostic.column);
+
+    // runs of the same frame, as deep recursion leaves, are printed once with a count
+    let mut frames = diagnostic.trace.iter().rev().peekable();
+    while let Some(frame) = frames.next() {
+        eprintln!("  in {}() called from [line {}]", frame.0, frame.1);
+
+        let mut repeated = 0;
+        while frames.next_if_eq(&frame).is_some() {
+            repeated += 1;
+        }
+        if repeated > 0 {
+            eprintln!("  ... repeated {} more times", repeated);
+        }
+    }
+}
+
+/// Print the source line with a caret under the given column, like:
+///
+/// ```text
+///  3 | print 1 +;
+///    |          ^
+/// ```
+///
+/// Tabs in the line are printed as spaces, so the caret lines up whatever the terminal's
+/// tab stops are.
+fn snippet(line: usize, column: usize) {
+    let column = display_column(line, column);
+
+    let source = match SOURCE.lock() {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+
+    let text = match source.lines().nth(line.wrapping_sub(1)) {
+        Some(text) => expand_tabs(text),
+        None => return,
+    };
+
+    let gutter = " ".repeat(line.to_string().len());
+    eprintln!(" {} | {}", line, text);
+    eprintln!(" {} | {}^", gutter, " ".repeat(column.saturating_sub(1)));
+}
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Color is used only when stderr is a terminal and `NO_COLOR` is unset or empty.
+fn use_color() -> bool {
+    color_wanted(env::var_os("NO_COLOR").as_deref(), io::stderr().is_terminal())
+}
+
+fn color_wanted(no_color: Option<&OsStr>, terminal: bool) -> bool {
+    let no_color = no_color.is_some_and(|value| !value.is_empty());
+    !no_color && terminal
+}
+
+/// Wrap text in an ANSI style, or leave it as-is when color is disabled.
+fn paint(style: &str, text: &str) -> String {
+    if use_color() {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `fuzz_run` takes what it reported back out of the shared diagnostics, so tests
+    // running at the same time mustn't interleave their reports
+    static FUZZING: Mutex<()> = Mutex::new(());
+
+    fn fuzz(source: &str, interpret: bool) -> Vec<Diagnostic> {
+        let _guard = FUZZING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        fuzz_run(source.as_bytes(), interpret)
+    }
+
+    fn assert_no_crash(diagnostics: &[Diagnostic]) {
+        for diagnostic in diagnostics {
+            assert!(
+                !diagnostic.message.starts_with("Internal error"),
+                "crashed: {}",
+                diagnostic.message
+            );
+        }
+    }
+
+    #[test]
+    fn odd_inputs_are_reported_without_crashing() {
+        let inputs = ["", "\"", "\"\"\"", "1e999999", "99999999999999999999999999999999999999999", "\u{feff}\0"];
+
+        for input in inputs {
+            assert_no_crash(&fuzz(input, false));
+            assert_no_crash(&fuzz(input, true));
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_crash() {
+        let parens = format!("print {}1{};", "(".repeat(20_000), ")".repeat(20_000));
+        let binary = format!("print 1{};", " + 1".repeat(20_000));
+        let postfix = format!("fn f() {{ return f; }} f{};", "()".repeat(20_000));
+
+        for source in [parens, binary, postfix] {
+            let diagnostics = fuzz(&source, true);
+            assert_no_crash(&diagnostics);
+            assert!(diagnostics.iter().any(|diagnostic| diagnostic.message.contains("nesting too deep")));
+        }
+    }
+
+    #[test]
+    fn runaway_recursion_is_a_runtime_error() {
+        let diagnostics = fuzz("let f = fn(n) { return 1 + f(n + 1); }; f(0);", true);
+
+        assert_no_crash(&diagnostics);
+        assert!(diagnostics.iter().any(|diagnostic| diagnostic.kind == Some(RuntimeErrorKind::StackOverflow)));
+    }
+
+    #[test]
+    fn long_regex_subjects_run() {
+        let source = format!(
+            r#"print regex_match("(a|b)*c", "{0}"); print regex_replace("a", "{0}", "x");"#,
+            "ab".repeat(50_000)
+        );
+
+        assert_no_crash(&fuzz(&source, true));
+    }
+
+    #[test]
+    fn no_color_turns_color_off_even_on_a_terminal() {
+        assert!(color_wanted(None, true));
+        assert!(color_wanted(Some(OsStr::new("")), true));
+        assert!(!color_wanted(Some(OsStr::new("1")), true));
+        assert!(!color_wanted(None, false));
+    }
+
+    #[test]
+    fn every_phase_is_timed_and_they_add_up_to_at_most_the_whole_run() {
+        let _guard = FUZZING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let source = "let total = 0; for (let i = 0; i < 2000; i = i + 1) { total = total + i; }";
+        let config = Config { time: true, ..Config::default() };
+
+        let start = Instant::now();
+        let timings = run_timed(source, &config, &mut config.interpreter());
+        let whole = start.elapsed();
+
+        let phases = [timings.lex, timings.parse, timings.resolve, timings.interpret];
+        assert!(phases.iter().all(|phase| !phase.is_zero()), "{:?}", timings);
+        assert!(phases.iter().sum::<Duration>() <= whole);
+    }
+
+    #[test]
+    fn a_lexical_and_a_parse_error_are_collected_with_their_lines() {
+        let diagnostics = fuzz("let a = 1 @ ;\n\nprint (;", false);
+
+        let found: Vec<(usize, &str)> =
+            diagnostics.iter().map(|diagnostic| (diagnostic.line, diagnostic.message.as_str())).collect();
+        assert_eq!(found, [(1, "Unexpected character: @"), (3, "Unable to parse the provided expression")]);
+        assert!(diagnostics.iter().all(|diagnostic| diagnostic.severity == Severity::Error));
+    }
+
+    #[test]
+    fn eval_expr_evaluates_one_expression_and_rejects_anything_after_it() {
+        let _guard = FUZZING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        assert_eq!(eval_expr("2 * (3 + 4)").unwrap(), Literal::Number(14.0));
+
+        let diagnostics = eval_expr("2 * 3 4").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Expected end of expression.");
+    }
+
+    #[test]
+    fn truncated_and_malformed_numbers_are_reported_without_crashing() {
+        let inputs = ["1.", "1e", "1e+", "0x", "0xG", "1_", "1__0", "1.2.3", ".5", "9", "print 1_"];
+
+        for input in inputs {
+            let diagnostics = fuzz(input, true);
+            assert_no_crash(&diagnostics);
+            assert!(!diagnostics.is_empty(), "{:?} wasn't reported", input);
+        }
+        assert!(fuzz("1_", false).iter().any(|diagnostic| diagnostic.message == "Invalid number: 1_"));
+    }
+}
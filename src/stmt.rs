@@ -1,4 +1,7 @@
-use crate::{lexer::Token, literal::Literal};
+use crate::{
+    lexer::{Token, TokenType},
+    literal::Literal,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
@@ -9,19 +12,45 @@ pub enum Expr {
     Literal(Literal),                   
     Variable(Token),                        // name
     Assign(Token, Box<Expr>),               // name, value
-    Call(Box<Expr>, Token, Vec<Expr>),      // callee, paren, list of argument
+    Call(Box<Expr>, Token, Vec<Expr>, Vec<(Token, Expr)>), // callee, paren, list of argument, keyword arguments
+    Array(Vec<Expr>),                       // list of elements
+    Map(Token, Vec<(Expr, Expr)>),          // brace, list of (key, value)
+    If(Box<Expr>, Box<Expr>, Box<Expr>),    // condition, then value, else value
+    Lambda(Box<LambdaDecl>),                // kept behind a box, as it is much larger than the other kinds
+    Index(Box<Expr>, Token, Box<Expr>),     // object, bracket, index
+    Get(Box<Expr>, Token, bool),            // object, property name, whether it is a `?.` access
+    Slice(Box<Expr>, Token, Box<Expr>, Box<Expr>), // object, bracket, start, end (Expr::None when omitted)
+    SetIndex(Box<Expr>, Token, Box<Expr>, Box<Expr>), // object, bracket, index, value
     None    
 }
 
+/// An anonymous function written as `fn (params) { ... }` or `fn (params) => value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LambdaDecl {
+    pub keyword: Token,
+    pub parameters: Vec<(Token, Option<Expr>)>, // name, default value
+    pub rest: Option<Token>,
+    pub body: Stmt,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
     Expression(Expr),                       // expression
-    If(Expr, Box<Stmt>, Box<Stmt>),         // condition, then branch, else branch
-    While(Expr, Box<Stmt>),                 // condition, body
-    Function(Token, Vec<Token>, Box<Stmt>), // name, params, body
+    If(Vec<(Expr, Stmt)>, Box<Stmt>),       // (condition, branch) for the `if` and each `else if`, else branch
+    While(Expr, Box<Stmt>, Expr, Box<Stmt>, Option<Token>), // condition, body, increment, else branch, label
+    DoWhile(Box<Stmt>, Expr, Option<Token>), // body, condition, label
+    Repeat(Token, Expr, Box<Stmt>, Option<Token>), // keyword, count, body, label
+    Switch(Expr, Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>), // subject, cases, default
+    Function(Token, Vec<(Token, Option<Expr>)>, Option<Token>, Box<Stmt>), // name, params with optional defaults, rest param, body
     Return(Token, Expr),                    // keyword, value
-    Print(Expr),                            // expression
+    Break(Token, Option<Token>),            // keyword, label
+    Continue(Token, Option<Token>),         // keyword, label
+    Print(Token, Expr),                     // keyword, expression
     Var(Token, Expr),                       // name, initializer
+    VarMulti(Vec<(Token, Expr)>),           // list of (name, initializer)
+    LetElse(Token, Expr, Box<Stmt>),        // name, initializer, branch run instead of binding a nil value
+    Const(Token, Expr),                     // name, initializer
+    Destructure(Token, Vec<Token>, Option<Token>, Expr), // bracket, names, rest name, initializer
     Block(Vec<Stmt>),                       // list of statement
     None
 }
@@ -33,4 +62,75 @@ impl Stmt {
             _ => None
         }
     }
+
+    /// For a function body written as `=> value`, the value. The parser turns such a body
+    /// into a block returning the value, with the `=>` as the return's keyword.
+    pub fn arrow_value(&self) -> Option<&Expr> {
+        match self.get_block_body()?.as_slice() {
+            [Stmt::Return(keyword, value)] if keyword.token_type == TokenType::FatArrow => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The line the statement starts on, when it holds a token to tell by.
+    /// Loops and conditionals report the line of their condition.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            // a block is only reported through the statements in it
+            Stmt::Block(_) => None,
+            _ => self.token().map(|token| token.line),
+        }
+    }
+
+    /// The first token in the statement, or in its condition for a loop or conditional,
+    /// for pointing at it in a diagnostic.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Stmt::Expression(expr) => expr.token(),
+            Stmt::If(arms, _) => arms.first().and_then(|(condition, _)| condition.token()),
+            Stmt::While(condition, ..) | Stmt::Switch(condition, ..) => condition.token(),
+            Stmt::DoWhile(_, condition, _) => condition.token(),
+            Stmt::Function(token, ..)
+            | Stmt::Return(token, _)
+            | Stmt::Break(token, _)
+            | Stmt::Continue(token, _)
+            | Stmt::Print(token, _)
+            | Stmt::Var(token, _)
+            | Stmt::LetElse(token, ..)
+            | Stmt::Repeat(token, ..)
+            | Stmt::Const(token, _)
+            | Stmt::Destructure(token, ..) => Some(token),
+            Stmt::VarMulti(declarations) => declarations.first().map(|(name, _)| name),
+            Stmt::Block(stmts) => stmts.iter().find_map(|stmt| stmt.token()),
+            Stmt::None => None,
+        }
+    }
+}
+
+impl Expr {
+    /// The first token in the expression, if it has any.
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Expr::Logical(left, operator, _) | Expr::Binary(left, operator, _) => {
+                left.token().or(Some(operator))
+            }
+            Expr::Unary(token, _)
+            | Expr::Variable(token)
+            | Expr::Assign(token, _)
+            | Expr::Map(token, _) => Some(token),
+            Expr::Lambda(lambda) => Some(&lambda.keyword),
+            Expr::Grouping(expr) => expr.token(),
+            Expr::Call(object, token, ..)
+            | Expr::Index(object, token, _)
+            | Expr::Get(object, token, _)
+            | Expr::Slice(object, token, ..)
+            | Expr::SetIndex(object, token, ..) => object.token().or(Some(token)),
+            Expr::Array(elements) => elements.iter().find_map(|element| element.token()),
+            Expr::If(condition, then_value, else_value) => condition
+                .token()
+                .or_else(|| then_value.token())
+                .or_else(|| else_value.token()),
+            Expr::Literal(_) | Expr::None => None,
+        }
+    }
 }
\ No newline at end of file
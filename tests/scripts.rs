@@ -0,0 +1,1198 @@
+//! Runs programs through the roz binary and checks what they print and how it exits.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+struct Output {
+    stdout: String,
+    stderr: String,
+    code: i32,
+}
+
+/// Run `source` read from stdin, with `flags` before the '-'.
+fn run(flags: &[&str], source: &str) -> Output {
+    let mut args = flags.to_vec();
+    args.push("-");
+    run_args(&args, source)
+}
+
+/// Run roz with exactly `args`, feeding it `input` on stdin.
+fn run_args(args: &[&str], input: &str) -> Output {
+    run_command(roz().args(args), input)
+}
+
+fn roz() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_roz"))
+}
+
+/// Run a roz command set up by the caller, feeding it `input` on stdin.
+fn run_command(command: &mut Command, input: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("roz should start");
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    Output {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        code: output.status.code().expect("roz should exit rather than be killed"),
+    }
+}
+
+/// Write `contents` to a file in the temp directory that no other test uses, named after `name`.
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("roz-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).expect("the temp file should be written");
+    path
+}
+
+#[test]
+fn regex_match_returns_the_match_and_groups() {
+    let output = run(&[], r#"print regex_match("(\d+)-(\d+)", "tel 555-1234");"#);
+    assert_eq!(output.stdout, "[555-1234, 555, 1234]\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn regex_match_without_a_match_is_nil() {
+    let output = run(&[], r#"print regex_match("x+", "abc"); print regex_match("a(b)?c", "ac");"#);
+    assert_eq!(output.stdout, "null\n[ac, null]\n");
+}
+
+#[test]
+fn regex_replace_expands_groups() {
+    let output = run(&[], r#"print regex_replace("(\w+)@(\w+)", "me@host you@there", "$2 at $1");"#);
+    assert_eq!(output.stdout, "host at me there at you\n");
+}
+
+#[test]
+fn invalid_pattern_is_a_runtime_error() {
+    let output = run(&[], r#"print regex_match("(a", "a");"#);
+    assert_eq!(output.code, 70);
+    assert!(output.stderr.contains("Unclosed '(' in pattern."), "{}", output.stderr);
+}
+
+#[test]
+fn regex_matching_does_not_backtrack_exponentially() {
+    let started = Instant::now();
+    let output = run(&[], r#"print regex_match("(a|a)*b", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");"#);
+    assert_eq!(output.stdout, "null\n");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn regex_matches_a_long_text_without_overflowing() {
+    let source = r#"
+        let s = "a";
+        repeat (18) s = s + s;
+        print regex_match("a*", s)[0] == s;
+    "#;
+    let output = run(&[], source);
+    assert_eq!(output.stdout, "true\n");
+}
+
+#[test]
+fn anonymous_functions_keep_the_scope_they_were_made_in() {
+    let source = r#"
+        fn adder(n) { return fn (x) => x + n; }
+        print adder(2)(3);
+
+        fn counter() {
+            let count = 0;
+            return fn () { count = count + 1; return count; };
+        }
+        let next = counter();
+        next();
+        print next();
+    "#;
+    let output = run(&[], source);
+    assert_eq!(output.stdout, "5\n2\n");
+}
+
+#[test]
+fn memo_calls_the_function_once_per_argument() {
+    let source = r#"
+        let calls = 0;
+        let square = memo(fn (n) { calls = calls + 1; return n * n; });
+        print square(4) + square(4) + square(5);
+        print calls;
+    "#;
+    let output = run(&[], source);
+    assert_eq!(output.stdout, "57\n2\n");
+}
+
+#[test]
+fn range_enumerate_and_zip_build_arrays() {
+    let source = r#"
+        print range(3);
+        print enumerate(["a", "b"]);
+        print zip([1, 2, 3], ["a", "b"]);
+        print zip([], [1]);
+    "#;
+    let output = run(&[], source);
+    assert_eq!(output.stdout, "[0, 1, 2]\n[[0, a], [1, b]]\n[[1, a], [2, b]]\n[]\n");
+}
+
+#[test]
+fn range_too_large_is_a_runtime_error() {
+    let output = run(&[], "print range(100000000000000000000);");
+    assert_eq!(output.code, 70);
+    assert!(output.stderr.contains("range can't count past 16777216."), "{}", output.stderr);
+}
+
+#[test]
+fn long_operator_and_postfix_chains_are_a_parse_error() {
+    let chains = [
+        format!("let a = [1]; print a{};", "[0]".repeat(20000)),
+        format!("fn f() {{ return f; }} print f{};", "()".repeat(20000)),
+        format!("print {};", vec!["1"; 20000].join(" + ")),
+        format!("print {};", vec!["true"; 20000].join(" and ")),
+    ];
+
+    for source in chains {
+        let output = run(&[], &source);
+        assert_eq!(output.code, 65);
+        assert!(output.stderr.contains("Expression nesting too deep."), "{}", output.stderr);
+    }
+}
+
+#[test]
+fn a_chain_of_ten_thousand_operators_runs() {
+    let output = run(&[], &format!("print {};", vec!["1"; 10001].join(" + ")));
+    assert_eq!(output.stdout, "10001\n");
+}
+
+#[test]
+fn json_diagnostics_go_to_stderr_apart_from_program_output() {
+    let output = run(&["--json-diagnostics"], "print \"hello\"; print nil + 1;");
+
+    assert_eq!(output.stdout, "hello\n");
+    assert!(output.stderr.trim_start().starts_with('['), "stderr: {}", output.stderr);
+    assert!(output.stderr.contains("\"runtime_error\""), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn json_diagnostics_can_be_written_to_a_file() {
+    let path = std::env::temp_dir().join(format!("roz-diagnostics-{}.json", std::process::id()));
+    let flag = format!("--json-diagnostics={}", path.display());
+
+    let output = run(&[&flag], "print \"hello\"; print nil + 1;");
+    let json = std::fs::read_to_string(&path).expect("the diagnostics file should be written");
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output.stdout, "hello\n");
+    assert_eq!(output.stderr, "");
+    assert!(json.contains("\"runtime_error\""), "file: {}", json);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn failed_asserts_under_test_are_rendered_as_fail_lines() {
+    let output = run(&["--test"], "assert(1 == 2, \"one is two\");\nassert(true);\nprint \"done\";");
+
+    assert_eq!(output.stdout, "done\n1 assertions passed, 1 failed\n");
+    assert!(output.stderr.contains("FAIL [line 1]: Assertion failed: one is two"), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn failed_asserts_under_test_are_in_the_json_diagnostics() {
+    let output = run(&["--test", "--json-diagnostics"], "assert(1 == 2, \"one is two\");");
+
+    assert!(output.stderr.contains("\"severity\": \"runtime_error\""), "stderr: {}", output.stderr);
+    assert!(output.stderr.contains("\"kind\": \"assertion_failed\""), "stderr: {}", output.stderr);
+    assert!(!output.stderr.contains("FAIL"), "stderr: {}", output.stderr);
+}
+
+#[test]
+fn and_and_or_never_evaluate_the_operand_they_skip() {
+    let source = r#"
+        let calls = 0;
+        fn f() { calls = calls + 1; return true; }
+        print false and f();
+        print true or f();
+        print calls;
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "false\ntrue\n0\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn a_variable_declared_in_a_block_is_gone_after_it() {
+    let output = run(&[], "{ let x = 1; } print x;");
+
+    assert!(output.stderr.contains("undefined variable 'x'"), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn a_block_can_shadow_an_outer_variable() {
+    let output = run(&[], "let x = 1; { let x = 2; print x; } print x;");
+
+    assert_eq!(output.stdout, "2\n1\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn an_array_returned_from_a_function_can_be_destructured() {
+    let output = run(&[], "fn pair() { return [1, 2]; } let [a, b] = pair(); print a; print b;");
+
+    assert_eq!(output.stdout, "1\n2\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn destructuring_into_the_wrong_number_of_names_is_an_error() {
+    let output = run(&[], "fn pair() { return [1, 2]; } let [a, b, c] = pair();");
+
+    assert!(output.stderr.contains("Expected 3 elements to destructure but got 2."), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn reading_a_local_in_its_own_initializer_is_a_compile_error() {
+    let output = run(&[], "print 1; { let a = a; }");
+
+    assert_eq!(output.stdout, "", "nothing runs when the resolver finds an error");
+    assert!(output.stderr.contains("Can't read local variable in its own initializer."), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 65);
+}
+
+#[test]
+fn redeclaring_a_local_in_the_same_scope_is_a_compile_error() {
+    let output = run(&[], "print 1; { let a = 1; let a = 2; }");
+
+    assert_eq!(output.stdout, "");
+    assert!(output.stderr.contains("Already a variable with this name in this scope."), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 65);
+}
+
+#[test]
+fn a_const_can_be_read_but_not_reassigned() {
+    let output = run(&[], "const x = 1; print x; x = 2; print \"unreachable\";");
+
+    assert_eq!(output.stdout, "1\n");
+    assert!(output.stderr.contains("Cannot assign to constant 'x'"), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn a_default_parameter_is_used_only_when_the_argument_is_left_out() {
+    let output = run(&[], "fn f(a, b = 10) { return a + b; } print f(1); print f(1, 2);");
+
+    assert_eq!(output.stdout, "11\n3\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn a_rest_parameter_collects_the_extra_arguments() {
+    let output = run(&[], "fn f(a, ...rest) { print rest; } f(1); f(1, 2, 3);");
+
+    assert_eq!(output.stdout, "[]\n[2, 3]\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn arguments_can_be_passed_by_name() {
+    let output = run(&[], "fn f(a, b) { return a - b; } print f(b: 1, a: 5); print f(5, b: 2);");
+
+    assert_eq!(output.stdout, "4\n3\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn an_unknown_keyword_argument_is_an_error() {
+    let output = run(&[], "fn f(a, b) { return a - b; } f(1, c: 2);");
+
+    assert!(output.stderr.contains("Unknown keyword argument 'c'."), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn switch_runs_only_the_matching_arm_or_the_default() {
+    let source = r#"
+        fn f(x) {
+            switch (x) {
+                case 1: print "one";
+                case 2: print "two";
+                default: print "other";
+            }
+        }
+        f(1); f(2); f(3);
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "one\ntwo\nother\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn do_while_runs_its_body_before_checking_the_condition() {
+    let source = r#"
+        let i = 0;
+        do { print i; } while (false);
+        do { i = i + 1; } while (i < 5);
+        print i;
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "0\n5\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn an_array_destructures_into_as_many_names() {
+    let output = run(&[], "let [a, b] = [1, 2]; print a + b;");
+
+    assert_eq!(output.stdout, "3\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn destructuring_a_shorter_array_is_an_error() {
+    let output = run(&[], "let [a, b] = [1];");
+
+    assert!(output.stderr.contains("Expected 2 elements to destructure but got 1."), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn an_if_expression_gives_the_value_of_the_branch_taken() {
+    let output = run(&[], "let x = if (true) 1 else 2; print x; print if (false) 1 else 2;");
+
+    assert_eq!(output.stdout, "1\n2\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn an_if_expression_without_else_is_a_parse_error() {
+    let output = run(&[], "let x = if (true) 1;");
+
+    assert!(output.stderr.contains("Expected 'else' branch in an 'if' expression."), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 65);
+}
+
+#[test]
+fn a_runtime_error_lists_each_call_it_passed_through() {
+    let source = "fn inner() { return nil + 1; }\nfn outer() { return inner(); }\nouter();";
+    let output = run(&[], source);
+
+    assert!(
+        output.stderr.contains("  in inner() called from [line 2]\n  in outer() called from [line 3]\n"),
+        "stderr: {}",
+        output.stderr
+    );
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn auto_semicolon_ends_statements_at_line_breaks() {
+    let output = run(&["--auto-semicolon"], "let x = 1\nprint x + 1\n");
+
+    assert_eq!(output.stdout, "2\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn one_let_can_declare_several_variables() {
+    let output = run(&[], "let a = 1, b, c = 3; print a; print b; print c;");
+
+    assert_eq!(output.stdout, "1\nnull\n3\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn div_rounds_the_quotient_down() {
+    let output = run(&[], "print 7 div 2; print -7 div 2; print 7 div -2;");
+
+    assert_eq!(output.stdout, "3\n-4\n-4\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn a_double_slash_comments_out_the_rest_of_the_line() {
+    let output = run(&[], "print 7 div 2;  // 3\nprint \"a//b\"; // not part of the string\n");
+
+    assert_eq!(output.stdout, "3\na//b\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn fmt_refuses_a_file_with_comments_rather_than_drop_them() {
+    let path = temp_file("comments.roz", "print 1;  // one\n");
+    let output = run_args(&["fmt", path.to_str().unwrap()], "");
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output.stdout, "");
+    assert!(output.stderr.contains("Can't format a file with comments"), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 65);
+}
+
+#[test]
+fn exit_ends_the_program_with_its_code() {
+    let output = run(&[], "print 1; exit(3); print 2;");
+
+    assert_eq!(output.stdout, "1\n");
+    assert_eq!(output.code, 3);
+}
+
+#[test]
+fn format_fills_in_placeholders_in_order() {
+    let output = run(&[], r#"print format("{} + {} = {}", 1, 2, 3); print format("{:2}", 3.14159);"#);
+
+    assert_eq!(output.stdout, "1 + 2 = 3\n3.14\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn an_error_quotes_its_line_with_a_caret_under_the_problem() {
+    let output = run(&[], "let a = 1;\nprint a + nil;\n");
+
+    assert!(
+        output.stderr.ends_with(" 2 | print a + nil;\n   |         ^\n"),
+        "stderr: {}",
+        output.stderr
+    );
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn the_prompt_shows_and_forgets_session_variables() {
+    let output = run_args(&["--repl"], "let x = 1\n:env\n:reset\n:env\nprint x\n\n");
+
+    // `:env` lists `x` before `:reset` and not after it, when reading it is an error
+    assert_eq!(output.stdout.matches("x => 1\n").count(), 1, "stdout: {}", output.stdout);
+    assert!(output.stderr.contains("undefined variable 'x'"), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn the_prompt_keeps_reading_until_brackets_are_closed() {
+    let output = run_args(&["--repl"], "fn twice(n) {\n  return n * 2\n}\nprint twice(4)\n");
+
+    assert_eq!(output.stdout, "#> ... ... #> 8\n#> ");
+    assert_eq!(output.stderr, "");
+}
+
+#[test]
+fn a_program_piped_in_without_a_filename_runs() {
+    let output = run_args(&[], "print 1 + 2;\nprint nil + 1;\n");
+
+    assert_eq!(output.stdout, "3\n");
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn an_assignment_used_as_a_condition_warns() {
+    let output = run(&[], "let x = 1; if (x = 5) print x;");
+
+    assert!(output.stderr.contains("Warning at 'x': Assignment used as a condition"), "stderr: {}", output.stderr);
+    assert_eq!(output.stdout, "5\n");
+}
+
+#[test]
+fn a_parenthesized_assignment_or_a_comparison_does_not_warn() {
+    let output = run(&[], "let x = 1; if ((x = 5)) print x; if (x == 5) print 2;");
+
+    assert_eq!(output.stderr, "");
+    assert_eq!(output.stdout, "5\n2\n");
+}
+
+#[test]
+fn ord_and_chr_convert_between_characters_and_code_points() {
+    let output = run(&[], r#"print ord("A"); print chr(65); print ord("λ"); print chr(955);"#);
+
+    assert_eq!(output.stdout, "65\nA\n955\nλ\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn strings_index_and_slice_by_character() {
+    let output = run(&[], r#"let s = "héllo"; print s[1]; print s[1..4];"#);
+
+    assert_eq!(output.stdout, "é\néll\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn indexing_past_the_end_of_a_string_is_an_error() {
+    let output = run(&[], r#"print "héllo"[9];"#);
+
+    assert!(output.stderr.contains("Index 9 is out of range for length 5."), "stderr: {}", output.stderr);
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn arrays_concatenate_with_plus_and_compare_by_contents() {
+    let output = run(&[], "print [1, 2] + [3]; print [[1, [2]]] == [[1, [2]]]; print [1, 2] == [1, 3];");
+
+    assert_eq!(output.stdout, "[1, 2, 3]\ntrue\nfalse\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn sort_orders_numbers_strings_or_by_a_comparator() {
+    let source = r#"
+        print sort([3, 1, 2]);
+        print sort(["b", "a"]);
+        print sort([1, 3, 2], fn (a, b) { return b - a; });
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "[1, 2, 3]\n[a, b]\n[3, 2, 1]\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn copy_is_deep_while_assignment_aliases() {
+    let source = r#"
+        let a = [[1], 2];
+        let b = copy(a);
+        b[0][0] = 9;
+        print a;
+        let c = a;
+        c[1] = 7;
+        print a;
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "[[1], 2]\n[[1], 7]\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn keys_values_has_and_remove_work_on_maps() {
+    let source = r#"
+        let m = {"a": 1};
+        m["b"] = 2;
+        print keys(m);
+        print values(m);
+        print has(m, "a");
+        print remove(m, "a");
+        print m;
+        print remove(m, "missing");
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "[a, b]\n[1, 2]\ntrue\n1\n{b: 2}\nnull\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn write_file_then_read_file_gives_back_the_contents() {
+    let path = std::env::temp_dir().join(format!("roz-{}-io.txt", std::process::id()));
+    let source = format!(r#"write_file("{0}", "two words"); print read_file("{0}");"#, path.display());
+    let output = run(&[], &source);
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output.stdout, "two words\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn args_gives_the_script_path_and_its_arguments() {
+    let path = temp_file("args.roz", "print args();");
+    let output = run_args(&[path.to_str().unwrap(), "one", "two"], "");
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output.stdout, format!("[{}, one, two]\n", path.display()));
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn env_reads_a_variable_or_gives_nil() {
+    let mut command = roz();
+    command.arg("-").env("ROZ_TEST_VALUE", "set").env_remove("ROZ_TEST_UNSET");
+    let output = run_command(&mut command, r#"print env("ROZ_TEST_VALUE"); print env("ROZ_TEST_UNSET");"#);
+
+    assert_eq!(output.stdout, "set\nnull\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn sleep_waits_at_least_as_long_as_asked() {
+    let output = run(&[], "let before = clock(); sleep(50); print clock() - before >= 0.05;");
+
+    assert_eq!(output.stdout, "true\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn functions_can_be_stored_called_and_compared() {
+    let source = r#"
+        fn a() { return 1; }
+        fn b() { return 2; }
+        let fs = [a, b];
+        print fs[1]();
+        let m = {"f": a};
+        print m["f"]();
+        print a == a;
+        print a == b;
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "2\n1\ntrue\nfalse\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn a_loop_else_runs_only_when_the_loop_was_not_broken_out_of() {
+    let source = r#"
+        fn find(items, target) {
+            for (let i = 0; i < 3; i = i + 1) {
+                if (items[i] == target) { print "found"; break; }
+            } else {
+                print "missing";
+            }
+        }
+        find([1, 2, 3], 2);
+        find([1, 2, 3], 5);
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "found\nmissing\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn labeled_break_and_continue_reach_the_outer_loop() {
+    let source = r#"
+        outer: for (let i = 0; i < 3; i = i + 1) {
+            for (let j = 0; j < 3; j = j + 1) {
+                if (j == 1) continue outer;
+                if (i == 2) break outer;
+                print i + "," + j;
+            }
+        }
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "0,0\n1,0\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn numbers_print_in_their_shortest_form() {
+    let output = run(&[], "print 0.1; print 100000000; print 2.5; print 1/0; print -1/0; print 0/0;");
+
+    assert_eq!(output.stdout, "0.1\n100000000\n2.5\nInfinity\n-Infinity\nNaN\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn zero_empty_strings_and_empty_arrays_are_falsy_only_under_strict_truthiness() {
+    let source = r#"
+        if (0) print "truthy"; else print "falsy";
+        if ("") print "truthy"; else print "falsy";
+        if ([]) print "truthy"; else print "falsy";
+        if (1) print "truthy"; else print "falsy";
+    "#;
+
+    assert_eq!(run(&[], source).stdout, "truthy\ntruthy\ntruthy\ntruthy\n");
+    assert_eq!(run(&["--strict-truthiness"], source).stdout, "falsy\nfalsy\nfalsy\ntruthy\n");
+}
+
+#[test]
+fn double_ampersand_and_double_bar_work_like_and_and_or() {
+    let output = run(&[], "print true && false; print true and false; print false || true; print false or true; print nil || 3;");
+
+    assert_eq!(output.stdout, "false\nfalse\ntrue\ntrue\n3\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn safe_navigation_stops_at_the_first_nil() {
+    let source = r#"
+        let full = {"b": {"c": 1}};
+        let broken = {"b": nil};
+        let missing = nil;
+        print full?.b?.c;
+        print broken?.b?.c;
+        print missing?.b?.c;
+        print broken.b.c;
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "1\nnull\nnull\n");
+    assert!(output.stderr.contains("Can't read property 'c' of a value of type null."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn entering_a_scope_does_not_copy_the_ones_around_it() {
+    let globals: String = (0..2000).map(|i| format!("let g{i} = {i};\n")).collect();
+    let blocks: String = (0..100)
+        .map(|depth| format!("{{ {}\n", (0..20).map(|k| format!("let v{depth}_{k} = {depth};")).collect::<String>()))
+        .collect();
+    let source = format!(
+        "{globals}{blocks}print g1999 + v0_0 + v99_19;\n{}\n\
+         let n = 0; for (let i = 0; i < 100000; i = i + 1) {{ let x = i; n = n + 1; }} print n;",
+        "}".repeat(100)
+    );
+
+    let started = Instant::now();
+    let output = run(&[], &source);
+
+    assert_eq!(output.stdout, "2098\n100000\n");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn a_tail_call_does_not_count_toward_the_call_depth() {
+    // well past the 20,000 calls deep at which a non-tail call overflows
+    let source = "fn count(n) { if (n == 0) return \"done\"; return count(n - 1); }\nprint count(100000);";
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "done\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn bang_flips_a_bool_but_minus_is_an_error() {
+    let output = run(&[], "print !true;\nprint -true;");
+
+    assert_eq!(output.stdout, "false\n");
+    assert!(output.stderr.contains("Cannot negate a bool."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn unary_plus_accepts_only_numbers() {
+    let output = run(&[], "print +5;\nprint +\"x\";");
+
+    assert_eq!(output.stdout, "5\n");
+    assert!(output.stderr.contains("Expected the operand to be a double, got 'string'."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn to_radix_and_from_radix_convert_between_bases() {
+    let output = run(&[], "print to_radix(255, 16); print from_radix(\"ff\", 16); print to_radix(-10, 2);\nprint to_radix(1, 37);");
+
+    assert_eq!(output.stdout, "ff\n255\n-1010\n");
+    assert!(output.stderr.contains("to_radix expects a base between 2 and 36."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn radix_conversions_past_a_64_bit_integer_are_out_of_range() {
+    let too_big = run(&[], "print to_radix(100000000000000000000, 2);");
+    assert!(too_big.stderr.contains("1e20 is out of range for to_radix."), "{}", too_big.stderr);
+    assert_eq!(too_big.code, 70);
+
+    let too_long = run(&[], "print from_radix(\"zzzzzzzzzzzzzzzzzzzz\", 36);");
+    assert!(too_long.stderr.contains("'zzzzzzzzzzzzzzzzzzzz' is out of range for from_radix."), "{}", too_long.stderr);
+    assert_eq!(too_long.code, 70);
+}
+
+#[test]
+fn string_searches_count_characters_not_bytes() {
+    let source = r#"
+        print contains("hello", "ell");
+        print contains("hello", "xyz");
+        print starts_with("hello", "he");
+        print ends_with("hello", "lo");
+        print index_of("größe", "e");
+        print index_of("hello", "z");
+        print contains(1, "a");
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "true\nfalse\ntrue\ntrue\n4\n-1\n");
+    assert!(output.stderr.contains("contains expects two strings."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn replace_changes_every_match_and_replace_first_only_one() {
+    let output = run(&[], "print replace(\"a-b-c\", \"-\", \"+\"); print replace_first(\"a-b-c\", \"-\", \"+\");\nprint replace(\"abc\", \"\", \"x\");");
+
+    assert_eq!(output.stdout, "a+b+c\na+b-c\n");
+    assert!(output.stderr.contains("replace can't replace an empty string."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn join_puts_the_separator_between_the_elements() {
+    let output = run(&[], "print join([1, 2, 3], \"-\"); print join([], \"-\") == \"\"; print join([nil, true, \"s\"], \", \");");
+
+    assert_eq!(output.stdout, "1-2-3\ntrue\nnull, true, s\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn a_seed_makes_random_numbers_repeat_and_they_stay_in_range() {
+    let source = r#"
+        seed(42);
+        let first = [random(), random(), random_int(1, 7)];
+        seed(42);
+        print first == [random(), random(), random_int(1, 7)];
+
+        let in_range = true;
+        for (let i = 0; i < 1000; i = i + 1) {
+            let r = random();
+            let n = random_int(1, 7);
+            if (r < 0 or r >= 1 or n < 1 or n >= 7 or n div 1 != n) in_range = false;
+        }
+        print in_range;
+        random_int(3, 3);
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "true\ntrue\n");
+    assert!(output.stderr.contains("random_int expects the lower bound to be less than the upper one."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn code_after_a_return_on_every_path_is_unreachable() {
+    let source = r#"
+fn f() {
+  return 1;
+  print "dead";
+}
+fn g(x) {
+  if (x) { return 1; } else { return 2; }
+  print "dead";
+}
+fn h(x) {
+  if (x) return 1;
+  print "live";
+}
+h(false);
+"#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "live\n");
+    assert!(output.stderr.contains("[Line 4] Warning at 'print': Unreachable code."), "{}", output.stderr);
+    assert!(output.stderr.contains("[Line 8] Warning at 'print': Unreachable code."), "{}", output.stderr);
+    assert!(!output.stderr.contains("[Line 12]"), "{}", output.stderr);
+    assert_eq!(output.code, 0);
+
+    let strict = run(&["--strict"], source);
+    assert!(strict.stderr.contains("[Line 4] Error at 'print': Unreachable code."), "{}", strict.stderr);
+    assert_eq!(strict.code, 65);
+}
+
+#[test]
+fn strict_mode_warns_about_a_function_that_can_fall_off_its_end() {
+    let both = "fn f(x) { if (x) { return 1; } else { return 2; } }\nprint f(true);";
+    let one = "fn f(x) { if (x) { return 1; } }\nprint f(false);";
+    let warning = "Function returns a value on some paths but reaches the end without returning on others";
+
+    let output = run(&["--strict"], both);
+    assert!(!output.stderr.contains(warning), "{}", output.stderr);
+    assert_eq!(output.stdout, "1\n");
+
+    let output = run(&["--strict"], one);
+    assert!(output.stderr.contains(&format!("[Line 1] Warning at 'f': {}", warning)), "{}", output.stderr);
+    assert_eq!(output.stdout, "null\n");
+
+    assert!(!run(&[], one).stderr.contains(warning));
+}
+
+#[test]
+fn a_switch_exits_only_when_it_has_a_default_and_every_arm_exits() {
+    let source = r#"
+fn f(x) { switch (x) { case 1: return 1; default: return 2; } }
+fn g(x) { switch (x) { case 1: return 1; case 2: print "two"; default: return 2; } }
+fn h(x) { switch (x) { case 1: return 1; } }
+print f(1);
+"#;
+    let output = run(&["--strict"], source);
+
+    assert!(!output.stderr.contains("Warning at 'f'"), "{}", output.stderr);
+    assert!(output.stderr.contains("Warning at 'g'"), "{}", output.stderr);
+    assert!(output.stderr.contains("Warning at 'h'"), "{}", output.stderr);
+    assert_eq!(output.stdout, "1\n");
+
+    let output = run(&[], "fn k(x) {\n    switch (x) { case 1: return 1; default: return 2; }\n    print \"dead\";\n}");
+    assert!(output.stderr.contains("[Line 3] Warning at 'print': Unreachable code."), "{}", output.stderr);
+}
+
+#[test]
+fn power_binds_tighter_than_times_and_groups_to_the_right() {
+    let output = run(&[], "print 2 ** 10; print 2 ** 3 ** 2; print 2 * 3 ** 2; print -2 ** 2; print 4 ** 0.5;\nprint \"a\" ** 2;");
+
+    assert_eq!(output.stdout, "1024\n512\n18\n-4\n2\n");
+    assert!(output.stderr.contains("Expected both operands to be double."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn eq_ignore_case_compares_strings_in_any_case() {
+    let output = run(&[], "print eq_ignore_case(\"STRASSE\", \"strasse\"); print eq_ignore_case(\"ÄÖÜ\", \"äöü\"); print eq_ignore_case(\"a\", \"b\");\nprint eq_ignore_case(1, \"1\");");
+
+    assert_eq!(output.stdout, "true\ntrue\nfalse\n");
+    assert!(output.stderr.contains("eq_ignore_case expects two strings."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn a_pipe_passes_its_left_side_as_the_first_argument() {
+    let source = "fn double(x) { return x * 2; } fn add(a, b) { return a + b; }\n\
+                  print 3 |> double |> add(1); print 1 + 2 |> double;\nprint 3 |> 4;";
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "7\n6\n");
+    assert!(output.stderr.contains("Couldn't execute function."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn there_is_no_shift_and_an_overflowing_product_is_infinite() {
+    let shift = run(&[], "print 1 << 100;");
+    assert!(shift.stderr.contains("Error at '<': Unable to parse the provided expression"), "{}", shift.stderr);
+    assert_eq!(shift.code, 65);
+
+    // numbers are floats in both modes, so a product too large to hold is infinite, not wrapped
+    let product = "print 100000000000000000000 * 100000000000000000000;";
+    for flags in [&[][..], &["--strict"]] {
+        let output = run(flags, product);
+        assert_eq!(output.stdout, "Infinity\n");
+        assert_eq!(output.code, 0);
+    }
+}
+
+#[test]
+fn a_literal_condition_warns_about_the_branch_it_rules_out() {
+    let source = "if (true) print \"a\"; else print \"b\";\nif (false) print \"c\"; else print \"d\";\nif ((true)) print \"e\";";
+
+    for flags in [&[][..], &["--strict"]] {
+        let output = run(flags, source);
+
+        assert_eq!(output.stdout, "a\nd\ne\n");
+        assert!(output.stderr.contains("[Line 1] Warning at 'print': Unreachable branch, the condition is always true."));
+        assert!(output.stderr.contains("[Line 2] Warning at 'print': Unreachable branch, the condition is always false."));
+        assert!(!output.stderr.contains("[Line 3]"), "{}", output.stderr);
+        assert_eq!(output.code, 0);
+    }
+}
+
+#[test]
+fn the_prompt_runs_the_statements_after_one_that_fails_but_a_file_stops() {
+    let source = "print 1 + nil; print 2; print 3;\n";
+
+    let prompt = run_args(&["--repl"], source);
+    assert!(prompt.stdout.contains("2\n3\n"), "stdout: {}", prompt.stdout);
+    assert!(prompt.stderr.contains("Cannot add 'number' and 'null'"), "stderr: {}", prompt.stderr);
+
+    let file = run(&[], source);
+    assert_eq!(file.stdout, "");
+    assert_eq!(file.code, 70);
+}
+
+#[test]
+fn a_return_outside_a_function_is_a_compile_error() {
+    let output = run(&[], "print \"before\";\nreturn 5;");
+
+    // reported before anything runs
+    assert_eq!(output.stdout, "");
+    assert!(output.stderr.contains("[Line 2] Error at 'return': Can't return from top-level code."));
+    assert_eq!(output.code, 65);
+}
+
+#[test]
+fn boolean_logical_makes_and_and_or_give_bools_and_still_short_circuit() {
+    let source = "print 1 and 2; print nil or \"x\"; print 1 and nil;\n\
+                  fn f() { print \"ran\"; return true; } print false and f(); print true or f();";
+
+    assert_eq!(run(&[], source).stdout, "2\nx\nnull\nfalse\ntrue\n");
+    assert_eq!(run(&["--boolean-logical"], source).stdout, "true\ntrue\nfalse\nfalse\ntrue\n");
+}
+
+#[test]
+fn ordering_a_number_against_a_string_is_an_error_but_equality_is_not() {
+    let output = run(&[], "print 1 < 2; print -1 <= -1; print 1 == \"a\";\nprint 1 < \"a\";");
+
+    assert_eq!(output.stdout, "true\ntrue\nfalse\n");
+    assert!(output.stderr.contains("Can only compare two numbers or two strings, got 'number' and 'string'."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn is_nil_agrees_with_comparing_to_nil() {
+    let source = "print is_nil(nil); print is_nil(0); print is_nil(nil) == (nil == nil); print is_nil(0) == (0 == nil);";
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "true\nfalse\ntrue\ntrue\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn strict_mode_warns_when_dividing_integers_gives_a_fraction() {
+    let warning = "Warning at '/': Dividing two integers with '/' gives a fraction, use 'div' for the integer quotient.";
+
+    let fraction = run(&["--strict"], "print 1 / 2;");
+    assert!(fraction.stderr.contains(warning), "{}", fraction.stderr);
+    assert_eq!(fraction.stdout, "0.5\n");
+
+    assert!(!run(&["--strict"], "print 4 / 2;").stderr.contains(warning));
+    assert!(!run(&[], "print 1 / 2;").stderr.contains(warning));
+}
+
+#[test]
+fn env_lists_variables_in_the_order_they_were_defined() {
+    let output = run_args(&["--repl"], "let zebra = 1; let apple = 2; let mango = 3;\n:env\n:env\n");
+
+    let dump = "zebra => 1\napple => 2\nmango => 3\n";
+    assert_eq!(output.stdout.matches(dump).count(), 2, "stdout: {}", output.stdout);
+}
+
+#[test]
+fn an_arrow_body_returns_its_expression() {
+    let output = run(&[], "fn double(x) => x * 2;\nlet inc = fn (x) => x + 1;\nprint double(21); print inc(1);");
+
+    assert_eq!(output.stdout, "42\n2\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn dbg_prints_its_argument_to_stderr_and_returns_it() {
+    let output = run(&[], "\nlet y = dbg(2 + 3) + 1;\nprint y;");
+
+    assert_eq!(output.stdout, "6\n");
+    assert_eq!(output.stderr, "[line 2] 5\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn character_classes_take_exactly_one_character() {
+    let source = r#"
+        print is_digit("7");
+        print is_digit("a");
+        print is_alpha("ß");
+        print is_alpha("1");
+        print is_space(" ");
+        print is_space("x");
+        print is_digit("12");
+    "#;
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "true\nfalse\ntrue\nfalse\ntrue\nfalse\n");
+    assert!(output.stderr.contains("is_digit expects a string of exactly one character."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn sign_clamp_and_pow_work_on_numbers() {
+    let source = "print sign(-3); print sign(0); print sign(2.5); print clamp(5, 0, 3); print clamp(-1, 0, 3);\n\
+                  print clamp(2, 0, 3); print pow(2, 8); print abs(-4);\nprint clamp(1, 3, 0);";
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "-1\n0\n1\n3\n0\n2\n256\n4\n");
+    assert!(output.stderr.contains("clamp expects lo to be no greater than hi."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn numbers_can_be_split_and_checked_for_nan_and_infinity() {
+    let source = "print trunc(3.7); print trunc(-3.7); print fract(3.25); print is_nan(0/0); print is_nan(1);\n\
+                  print is_infinite(1/0); print is_infinite(-1/0); print is_infinite(1);\nprint trunc(\"x\");";
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "3\n-3\n0.25\ntrue\nfalse\ntrue\ntrue\nfalse\n");
+    assert!(output.stderr.contains("trunc expects a number."));
+    assert_eq!(output.code, 70);
+}
+
+#[test]
+fn implicit_return_gives_the_last_expression_unless_a_return_comes_first() {
+    let source = r#"
+        fn implicit() { 1 + 1; }
+        fn explicit() { return 3; 1; }
+        fn either(x) { if (x) return "early"; "late"; }
+        print implicit(); print explicit(); print either(true); print either(false);
+    "#;
+
+    assert_eq!(run(&[], source).stdout, "null\n3\nearly\nnull\n");
+    assert_eq!(run(&["--implicit-return"], source).stdout, "2\n3\nearly\nlate\n");
+}
+
+#[test]
+fn a_tab_moves_the_display_column_to_the_next_tab_stop() {
+    let source = "\t\tlet x = @;";
+
+    let default = run(&["--json-diagnostics"], source);
+    assert!(default.stderr.contains("\"line\": 1, \"column\": 11, \"display_column\": 17"), "{}", default.stderr);
+
+    let narrow = run(&["--json-diagnostics", "--tab-width=2"], source);
+    assert!(narrow.stderr.contains("\"line\": 1, \"column\": 11, \"display_column\": 13"), "{}", narrow.stderr);
+
+    // the quoted line has its tabs expanded, so the caret lines up under the '@'
+    let rendered = run(&[], "\tx @;");
+    assert!(rendered.stderr.contains(" 1 |     x @;\n   |       ^\n"), "{}", rendered.stderr);
+}
+
+#[test]
+fn a_shebang_first_line_is_skipped_but_still_counted() {
+    let path = temp_file("shebang.roz", "#!/usr/bin/env roz\nprint \"hi\";\nprint nil + 1;\n");
+    let output = run_args(&[path.to_str().unwrap()], "");
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output.stdout, "hi\n");
+    assert!(output.stderr.contains("[line 3]"), "{}", output.stderr);
+    assert_eq!(output.code, 70);
+
+    let elsewhere = run(&[], "print 1;\n#!x");
+    assert!(elsewhere.stderr.contains("[Line 2] Error: Unexpected character: #"), "{}", elsewhere.stderr);
+}
+
+#[test]
+fn fmt_keeps_a_shebang_first_line() {
+    let path = temp_file("shebang-fmt.roz", "#!/usr/bin/env roz\nprint   \"hi\" ;\n");
+    let output = run_args(&["fmt", path.to_str().unwrap()], "");
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output.stdout, "#!/usr/bin/env roz\nprint \"hi\";\n");
+    assert_eq!(output.code, 0);
+}
+
+#[test]
+fn parse_number_accepts_what_the_lexer_does() {
+    let output = run(&[], "print parse_number(\"1_000\"); print parse_number(\"-2.5\"); print 1_000;");
+    assert_eq!(output.stdout, "1000\n-2.5\n1000\n");
+    assert_eq!(output.code, 0);
+
+    // there are no hex literals, so neither takes `0xFF`
+    for text in ["0xFF", "bad"] {
+        let output = run(&[], &format!("parse_number(\"{}\");", text));
+        assert!(output.stderr.contains(&format!("'{}' is not a valid number.", text)), "{}", output.stderr);
+        assert_eq!(output.code, 70);
+    }
+    assert_eq!(run(&[], "print 0xFF;").code, 65);
+}
+
+#[test]
+fn let_else_binds_a_value_or_leaves_through_its_else_branch() {
+    let source = "fn f(v) { let x = v else { return \"none\"; }; return x; }\nprint f(1); print f(nil); print f(false);";
+    let output = run(&[], source);
+    assert_eq!(output.stdout, "1\nnone\nfalse\n");
+    assert_eq!(output.code, 0);
+
+    let falls_through = run(&[], "let x = nil else { print \"no\"; };");
+    assert!(falls_through.stderr.contains("The else branch of 'let ... else' must leave with return, break or continue."));
+    assert_eq!(falls_through.code, 65);
+}
+
+#[test]
+fn repeat_runs_its_body_a_whole_number_of_times() {
+    let source = "repeat (3) print \"hi\";\n\
+                  let n = 0; repeat (5) { n = n + 1; if (n == 2) continue; if (n == 4) break; print n; }\n\
+                  repeat (1.5) print \"x\";";
+    let output = run(&[], source);
+
+    assert_eq!(output.stdout, "hi\nhi\nhi\n1\n3\n");
+    assert!(output.stderr.contains("Repeat count must be a non-negative integer, got 1.5."));
+    assert_eq!(output.code, 70);
+}